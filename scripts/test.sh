@@ -1,16 +1,17 @@
 #!/bin/bash
 
 debug="$2"
+opt="-O${3:-0}"
 
 cargo build
 assert() {
     expected="$1"
     input="$2"
     if [ "$debug" = "true" ]; then
-        ./target/debug/nonicc "int main() { $input }" -d > tmp.s
+        ./target/debug/nonicc -S --expr "int main() { $input }" "$opt" -d > tmp.s
         cat tmp.s
     else
-        ./target/debug/nonicc "int main() { $input }" > tmp.s
+        ./target/debug/nonicc -S --expr "int main() { $input }" "$opt" > tmp.s
     fi
     gcc -z noexecstack -o tmp tmp.s
         ./tmp
@@ -40,6 +41,10 @@ assert 42 " return 300 / 7; "
 assert 8 " return (1 + 2) * 3 - (4 + 5) - 6 / 7 + 8; "
 assert 42 " return ((((42) - 42)) + 42); "
 
+# レジスタが足りない場合はスピルする
+assert 45 " return 1 + (2 + (3 + (4 + (5 + (6 + (7 + (8 + 9))))))); "
+assert 149 " return (1 + (2 + (3 + (4 + (5 + (6 + (7 + (8 + 9)))))))) * ((1 + 2) * (3 + (4 / (2 + (2 * (1 + 1)))))); "
+
 assert 1 " return 0 - -1; "
 assert 1 " return 0 + +1; "
 assert 42 " return (1 + +2 / -3) * (-4 / -5 - -6 * +7); "
@@ -380,31 +385,30 @@ return sum;
 
 fi
 
-# 現在の割り当てアルゴリズムでは
-# コードが正しくても通らないのでスキップ
-# assert 3 "
-# sum = 0;
-# for (x = 3; (x = x - 1) > 0; ) {
-#     sum = sum + x;
-# }
-# return sum;
-# "
+# 条件式の中で代入した値を使う
+assert 3 "
+int sum; int x; sum = 0;
+for (x = 3; (x = x - 1) > 0; ) {
+    sum = sum + x;
+}
+return sum;
+"
 
 assert 3 "
-int x; int y; x = 3;
+int x; int *y; x = 3;
 y = &x;
 return *y;
 "
 
 assert 3 "
-int x; int y; int z; x = 3;
+int x; int *y; int **z; x = 3;
 y = &x;
 z = &y;
 return **z;
 "
 
 assert 3 "
-int x; int y; x = 1;
+int x; int *y; x = 1;
 y = &x;
 *y = 3;
 return x;
@@ -457,6 +461,64 @@ b = 30;
 return *p;
 "
 
+# ポインタの足し算・引き算は指す先の大きさ単位
+# 後に宣言した変数ほど低いアドレスに置かれる
+assert 7 "int x; int y; int *p; int *q; p = &x; q = &y; *(q + 1) = 7; return *p;"
+assert 5 "int x; int y; int *p; int *q; p = &x; q = &y; *(p - 1) = 5; return *q;"
+assert 3 "int x; int y; int *p; int *q; p = &x; q = &y; *(1 + q) = 3; return *p;"
+assert 1 "int x; int y; return &x - &y;"
+assert 3 "int x; int *p; p = &x; return (p + 3) - p;"
+assert 2 "int x; int *p; int *q; p = &x; q = p + 2; return q - p;"
+assert 1 "int x; int *p; int **pp; p = &x; pp = &p; *pp = *pp + 1; return p - &x;"
+
+# intは32bitで計算する
+assert 1 "int x; x = 2147483647; x = x + 1; return x < 0;"
+assert 1 "int x; x = 2147483647; return x + 1 < 0;"
+assert 4 "int x; int y; return (&x - &y) * 4;"
+assert 3 "int x; x = 0 - 7; return 0 - x / 2;"
+
+# charは1バイトで読み書きする
+assert 3 "char c; c = 3; return c;"
+assert 44 "char c; c = 300; return c;"
+assert 1 "char c; c = 127; c = c + 1; return c < 0;"
+assert 1 "char c; c = 255; return c == 0 - 1;"
+assert 5 "char x; char y; char *p; p = &y; *(p + 1) = 5; return x;"
+assert 1 "char x; char y; return &x - &y;"
+assert 7 "char c; int x; char *p; x = 0; p = &c; *p = 7; return x + c;"
+assert 44 "char c; char *p; p = &c; *p = 300; return *p;"
+
+# 配列
+assert 3 "int a[2]; *a = 1; *(a + 1) = 2; return *a + *(a + 1);"
+assert 5 "int a[3]; a[0] = 2; a[2] = 3; return a[0] + a[2];"
+assert 45 "int a[10]; int i; for (i = 0; i < 10; i = i + 1) a[i] = i; int s; s = 0; for (i = 0; i < 10; i = i + 1) s = s + a[i]; return s;"
+assert 3 "int a[4]; int *p; p = a; p[3] = 3; return a[3];"
+assert 2 "int a[4]; return &a[3] - &a[1];"
+assert 51 "int a[2][3]; int i; int j; for (i = 0; i < 2; i = i + 1) for (j = 0; j < 3; j = j + 1) a[i][j] = i * 3 + j; return a[1][2] * 10 + a[0][1];"
+assert 6 "char s[4]; s[0] = 1; s[1] = 2; s[2] = 3; return s[0] + s[1] + s[2];"
+assert 7 "int x; int a[3]; int y; x = 7; y = 9; a[0] = 1; a[1] = 2; a[2] = 3; return x + y - 9;"
+
+# 構造体と共用体
+assert 7 "struct { int x; int y; } p; p.x = 3; p.y = 4; return p.x + p.y;"
+assert 8 "struct { char c; int x; } s; s.c = 1; s.x = 7; return s.c + s.x;"
+assert 12 "struct { char c; int x; char d; } s; return &s.d - &s.c + 4;"
+assert 3 "struct P { int x; int y; }; struct P a; struct P *q; q = &a; q->y = 3; return a.y;"
+assert 5 "struct P { int x; int a[3]; }; struct P s; s.a[2] = 5; return s.a[2];"
+assert 9 "struct P { int x; char c; }; struct P a; struct P b; a.x = 4; a.c = 5; b = a; a.x = 0; return b.x + b.c;"
+assert 3 "union { int x; char c; } u; u.x = 259; return u.c;"
+assert 6 "struct A { int x; }; struct B { struct A a; int y; }; struct B b; b.a.x = 2; b.y = 4; return b.a.x + b.y;"
+assert 2 "struct P { int x; }; struct P a[3]; a[1].x = 2; struct P *p; p = a; return (p + 1)->x;"
+
+# 文字列リテラルと文字リテラル
+assert 97 "return 'a';"
+assert 10 "return '\\n';"
+assert 1 "return '\\377' == 0 - 1;"
+assert 98 "char *s; s = \"abc\"; return s[1];"
+assert 0 "return \"abc\"[3];"
+assert 9 "return \"a\\tb\"[1];"
+assert 65 "return \"\\101\\x42\"[0];"
+assert 66 "return \"\\101\\x42\"[1];"
+assert 100 "char *s; s = \"ab\" \"cd\"; return s[3];"
+
 rm -f tmp*
 
 echo OK
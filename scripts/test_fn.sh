@@ -1,15 +1,16 @@
 #!/bin/bash
 debug="$1"
+opt="-O${2:-0}"
 
 cargo build
 assert() {
     expected="$1"
     input="$2"
     if [ "$debug" = "true" ]; then
-        ./target/debug/nonicc "$input" -d > tmp.s
+        ./target/debug/nonicc -S --expr "$input" "$opt" -d > tmp.s
         cat tmp.s
     else
-        ./target/debug/nonicc "$input" > tmp.s
+        ./target/debug/nonicc -S --expr "$input" "$opt" > tmp.s
     fi
     gcc -z noexecstack -o tmp tmp.s
     ./tmp
@@ -148,6 +149,217 @@ int main() {
 }
 "
 
+assert 45 "
+int add(int a, int b) {
+    return a + b;
+}
+
+int main() {
+    return 1 + (2 + (3 + (4 + (5 + (6 + (7 + add(8, 9)))))));
+}
+"
+
+assert 25 "
+struct P {
+    int x;
+    char c;
+    int *p;
+};
+
+int sum(int n) {
+    struct P a;
+    struct P b;
+    struct P *q;
+    a.x = n;
+    a.c = 4;
+    a.p = &n;
+    b = a;
+    q = &b;
+    q->x = q->x + 10;
+    return q->x + q->c + *q->p;
+}
+
+int main() {
+    return sum(3) + 5;
+}
+"
+
+# 自分へのポインタを持つ構造体と前方宣言した構造体
+assert 15 "
+struct Node {
+    int v;
+    struct Node *next;
+};
+
+struct S;
+struct S *gp;
+struct S { int a; int b; };
+
+int main() {
+    struct Node a;
+    struct Node b;
+    struct Node c;
+    struct Node *n;
+    struct S s;
+    int sum;
+    a.v = 1;
+    b.v = 2;
+    c.v = 4;
+    a.next = &b;
+    b.next = &c;
+    c.next = 0;
+    sum = 0;
+    for (n = &a; n != 0; n = n->next)
+        sum = sum + n->v;
+    gp = &s;
+    gp->b = 8;
+    return sum + s.b;
+}
+"
+
+assert 17 "
+int g;
+int h = 5;
+char c = -1;
+int *p;
+int a[4];
+struct P { int x; char y; } s;
+int set(int v) { g = v; return 0; }
+int main() {
+  int h2;
+  set(3);
+  p = &g;
+  *p = *p + 1;
+  a[2] = 7;
+  s.y = 2;
+  h2 = h;
+  return g + a[2] + s.y + h2 + c;
+}
+"
+
+# プロトタイプ宣言で定義より前に呼び出す
+assert 21 "
+int is_odd(int n);
+
+int is_even(int n) {
+    if (n == 0) return 1;
+    return is_odd(n - 1);
+}
+
+int is_odd(int n) {
+    if (n == 0) return 0;
+    return is_even(n - 1);
+}
+
+int main() {
+    return is_even(10) * 20 + is_odd(7);
+}
+"
+
+# 標準ライブラリの関数はリンク時に解決する
+assert 9 "
+int printf(char *fmt, ...);
+extern int strlen(char *s), puts(char *s);
+
+int main() {
+    puts(\"hello\");
+    return printf(\"%d %s\\n\", 42, \"abc\") + strlen(\"ab\");
+}
+"
+
+# ()の宣言は引数を決めず, 後の定義で決まる
+assert 42 "
+int f();
+
+int main() {
+    return f(40);
+}
+
+int f(int a) {
+    return a + 2;
+}
+"
+
+# 7個目以降の引数はスタックで渡す
+assert 28 "
+int sum7(int a, int b, int c, int d, int e, int f, int g) {
+    return a + b + c + d + e + f + g;
+}
+
+int main() {
+    return sum7(1, 2, 3, 4, 5, 6, 7);
+}
+"
+
+assert 59 "
+int sub8(int a, int b, int c, int d, int e, int f, int g, int h) {
+    return a - b + c - d + e - f + g * 10 - h;
+}
+
+int main() {
+    return sub8(1, 2, 3, 4, 5, 6, 7, 8);
+}
+"
+
+# 使われない引数があっても他の引数は壊れない
+assert 19 "
+int f(int a, int b, int c, int d, int e, int f, int g) {
+    return a * 2 + g * 3 + f / 2;
+}
+
+int main() {
+    return f(1, 1, 1, 1, 1, 4, 5);
+}
+"
+
+assert 18 "
+int printf(char *fmt, ...);
+
+int main() {
+    return printf(\"%d%d%d%d%d%d%d%d\\n\", 1, 2, 3, 4, 5, 6, 7, 8) * 2;
+}
+"
+
+# ポインタ, 配列, charの引数を受け取る
+assert 7 "
+int set(int *p, int v) {
+    *p = v;
+    return 0;
+}
+
+int main() {
+    int a;
+    set(&a, 7);
+    return a;
+}
+"
+
+assert 12 "
+int sum(int a[3], char c) {
+    return a[0] + a[1] + a[2] + c;
+}
+
+int main() {
+    int x[3];
+    x[0] = 1;
+    x[1] = 2;
+    x[2] = 3;
+    return sum(x, 6);
+}
+"
+
+assert 9 "
+int last(int a, int b, int c, int d, int e, int f, int *p) {
+    return *p + a;
+}
+
+int main() {
+    int x;
+    x = 8;
+    return last(1, 2, 3, 4, 5, 6, &x);
+}
+"
+
 rm -f tmp*
 
 echo OK
\ No newline at end of file
@@ -0,0 +1,263 @@
+use std::fmt;
+
+use crate::source_map::SourceMap;
+use crate::types::Span;
+
+/// 診断の重大度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// 診断の種類を表すエラーコード
+/// - `E00xx`: 字句解析
+/// - `E01xx`: 構文解析
+/// - `E02xx`: 意味解析
+/// - `E09xx`: コンパイラ内部のエラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// トークナイズできない文字
+    InvalidChar,
+    /// 数値が大きすぎる
+    NumberTooLarge,
+    /// 閉じられていない文字列・文字リテラル
+    UnterminatedLiteral,
+    /// 不正なエスケープシーケンス
+    InvalidEscape,
+    /// 空の文字リテラル
+    EmptyCharLiteral,
+    /// 想定したトークンが無い
+    ExpectedToken,
+    /// 数値が必要
+    ExpectedNumber,
+    /// 識別子が必要
+    ExpectedIdent,
+    /// 型が必要
+    ExpectedType,
+    /// 対応する`{`が無い`}`
+    UnmatchedBrace,
+    /// 宣言されていない変数
+    UndeclaredVar,
+    /// 定義されていない関数
+    UndefinedFn,
+    /// 関数の重複定義
+    Redefinition,
+    /// 代入できない左辺
+    NotAssignable,
+    /// アドレスを取れない値
+    NotAddressable,
+    /// 参照外しできない値
+    NotDereferenceable,
+    /// 代入で型が合わない
+    IncompatibleTypes,
+    /// 演算子や条件式に使えない型
+    InvalidOperands,
+    /// 関数の引数の数が合わない
+    ArgCountMismatch,
+    /// 構造体・共用体に無いメンバの参照
+    NoMember,
+    /// 定義前の構造体・共用体 (不完全型) を値として使った
+    IncompleteType,
+    /// コンパイラ内部のエラー
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        // 忘れてバグの原因になるためワイルドカードを使わない
+        match self {
+            ErrorCode::InvalidChar => "E0001",
+            ErrorCode::NumberTooLarge => "E0002",
+            ErrorCode::UnterminatedLiteral => "E0003",
+            ErrorCode::InvalidEscape => "E0004",
+            ErrorCode::EmptyCharLiteral => "E0005",
+            ErrorCode::ExpectedToken => "E0101",
+            ErrorCode::ExpectedNumber => "E0102",
+            ErrorCode::ExpectedIdent => "E0103",
+            ErrorCode::ExpectedType => "E0104",
+            ErrorCode::UnmatchedBrace => "E0105",
+            ErrorCode::UndeclaredVar => "E0201",
+            ErrorCode::UndefinedFn => "E0202",
+            ErrorCode::Redefinition => "E0203",
+            ErrorCode::NotAssignable => "E0204",
+            ErrorCode::NotAddressable => "E0205",
+            ErrorCode::NotDereferenceable => "E0206",
+            ErrorCode::IncompatibleTypes => "E0207",
+            ErrorCode::InvalidOperands => "E0208",
+            ErrorCode::ArgCountMismatch => "E0209",
+            ErrorCode::NoMember => "E0210",
+            ErrorCode::IncompleteType => "E0211",
+            ErrorCode::Internal => "E0901",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// 字句解析・構文解析で見つかった問題
+/// - 表示はしない (呼び出し側で`render`して表示する)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { code, message: message.into(), span, severity: Severity::Error }
+    }
+
+    /// gcc/clangと同じ形式で表示用の文字列にする
+    /// - `file.c:行:列: error: メッセージ`
+    /// - 列はgccと同じくバイト単位で数える
+    /// - 該当する行だけを表示し, トークンの範囲に`^~~`で下線を引く
+    pub fn render(&self, map: &SourceMap) -> String {
+        let (line, col) = map.line_col(self.span.start);
+        let text = map.line_text(line);
+
+        // 列はバイト単位なので, 行の中のバイト位置までの文字で字下げする
+        // タブの幅がずれないようにタブはそのまま残す
+        let byte_col = (col - 1).min(text.len());
+        let indent: String = text.get(..byte_col).unwrap_or("").chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // 下線はトークンの文字数分で, 行の終わりまでにする (EOFなどは1文字分)
+        let end = (byte_col + self.span.end.saturating_sub(self.span.start)).min(text.len());
+        let len = text.get(byte_col..end).unwrap_or("").chars().count().max(1);
+        let underline = format!("^{}", "~".repeat(len - 1));
+
+        format!("{}:{}:{}: {}: {}\n{}\n{}{}", map.name, line, col, self.severity, self.message, text, indent, underline)
+    }
+
+    /// 1行のJSONオブジェクトにする (`--diagnostics-format=json`)
+    pub fn to_json(&self, map: &SourceMap) -> String {
+        let (line, col) = map.line_col(self.span.start);
+        format!(
+            "{{\"file\":{},\"range\":{{\"start\":{},\"end\":{}}},\"line\":{},\"column\":{},\"severity\":\"{}\",\"code\":\"{}\",\"message\":{}}}",
+            json_string(map.name), self.span.start, self.span.end, line, col, self.severity, self.code, json_string(&self.message),
+        )
+    }
+}
+
+/// JSONの文字列リテラルにする
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// 一度に見つかった複数の診断
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// 全ての診断を表示用の文字列にする
+    pub fn render(&self, map: &SourceMap) -> String {
+        self.0.iter().map(|d| d.render(map)).collect::<Vec<_>>().join("\n")
+    }
+
+    /// 1行に1つずつJSONオブジェクトを並べる
+    pub fn to_json(&self, map: &SourceMap) -> String {
+        self.0.iter().map(|d| d.to_json(map)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(d: Diagnostic) -> Self {
+        Diagnostics(vec![d])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msgs: Vec<String> = self.0.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", msgs.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[test]
+/// 該当する行だけを表示してトークンに下線を引く
+fn test_render() {
+    let src = "int main() {\n  return foo;\n}";
+    let map = SourceMap::new("a.c", src);
+    let start = src.find("foo").unwrap();
+    let d = Diagnostic::error(ErrorCode::UndeclaredVar, "Use of undeclared identifier 'foo'", Span { start, end: start + 3 });
+
+    let expected = "\
+a.c:2:10: error: Use of undeclared identifier 'foo'
+  return foo;
+         ^~~";
+    assert_eq!(d.render(&map), expected);
+}
+
+#[test]
+/// 前に非ASCII文字があっても下線の位置と長さは文字単位で合わせる
+fn test_render_non_ascii() {
+    let src = "char *s; s = \"ああ\"; return *s + foo;";
+    let map = SourceMap::new("a.c", src);
+    let start = src.find("foo").unwrap();
+    let d = Diagnostic::error(ErrorCode::UndeclaredVar, "Use of undeclared identifier 'foo'", Span { start, end: start + 3 });
+
+    let expected = "\
+a.c:1:36: error: Use of undeclared identifier 'foo'
+char *s; s = \"ああ\"; return *s + foo;
+                               ^~~";
+    assert_eq!(d.render(&map), expected);
+
+    let start = src.find('"').unwrap();
+    let d = Diagnostic::error(ErrorCode::IncompatibleTypes, "Incompatible types", Span { start, end: start + 8 });
+    let expected = "\
+a.c:1:14: error: Incompatible types
+char *s; s = \"ああ\"; return *s + foo;
+             ^~~~";
+    assert_eq!(d.render(&map), expected);
+}
+
+#[test]
+/// JSONでは文字列をエスケープする
+fn test_to_json() {
+    let src = "int main() {\n  return \"a\";\n}";
+    let map = SourceMap::new("dir\\a.c", src);
+    let start = src.find('"').unwrap();
+    let d = Diagnostic::error(ErrorCode::InvalidChar, "トークナイズできません: \"", Span { start, end: start + 1 });
+
+    let expected = r#"{"file":"dir\\a.c","range":{"start":22,"end":23},"line":2,"column":10,"severity":"error","code":"E0001","message":"トークナイズできません: \""}"#;
+    assert_eq!(d.to_json(&map), expected);
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{bail, Context, Result};
+
+/// 入力ファイルの種類
+/// - 拡張子で判断する
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputKind {
+    /// C言語のソースコード (標準入力を含む)
+    Source,
+    /// アセンブリ (`.s`)
+    Asm,
+    /// オブジェクトファイル (`.o`)
+    Object,
+}
+
+impl InputKind {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("s") => InputKind::Asm,
+            Some("o") => InputKind::Object,
+            _ => InputKind::Source,
+        }
+    }
+}
+
+/// 入力ファイルに対応する出力ファイル名を返す
+/// - `dir/foo.c`なら`foo.<ext>` (カレントディレクトリに置く)
+/// - 標準入力のときは`a.<ext>`
+pub fn output_path(input: &str, ext: &str) -> PathBuf {
+    let stem = match input {
+        "-" => "a",
+        _ => Path::new(input).file_stem().and_then(|s| s.to_str()).unwrap_or("a"),
+    };
+    PathBuf::from(format!("{}.{}", stem, ext))
+}
+
+/// `-S`で書き出すアセンブリの出力先
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmOutput {
+    Stdout,
+    File(PathBuf),
+}
+
+impl AsmOutput {
+    /// `-o`と入力ファイルから出力先を決める
+    /// - `-o -`のときは標準出力
+    /// - `-o`が無く標準入力から読んだときも標準出力
+    pub fn new(output: Option<&str>, input: &str) -> Self {
+        match (output, input) {
+            (Some("-"), _) | (None, "-") => AsmOutput::Stdout,
+            (Some(path), _) => AsmOutput::File(PathBuf::from(path)),
+            (None, input) => AsmOutput::File(output_path(input, "s")),
+        }
+    }
+}
+
+/// 一時ファイルの置き場所
+/// - dropしたときに作ったファイルを全て消す
+pub struct TempFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl TempFiles {
+    pub fn new() -> Self {
+        TempFiles { paths: Vec::new() }
+    }
+
+    /// 一時ファイルのパスを予約する
+    /// - ファイル自体は作らない
+    pub fn path(&mut self, ext: &str) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("nonicc-{}-{}.{}", std::process::id(), count, ext);
+        let path = std::env::temp_dir().join(name);
+        self.paths.push(path.clone());
+        path
+    }
+}
+
+impl Default for TempFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            // 作られなかったファイルもあるので失敗は無視する
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// `as`でアセンブリをオブジェクトファイルにする
+pub fn assemble(asm: &Path, obj: &Path) -> Result<()> {
+    let mut cmd = Command::new("as");
+    cmd.arg("--noexecstack").arg("-o").arg(obj).arg(asm);
+    run_tool(&mut cmd)
+}
+
+/// `cc`でオブジェクトファイルをリンクして実行ファイルにする
+pub fn link(objs: &[PathBuf], exe: &Path) -> Result<()> {
+    let mut cmd = Command::new("cc");
+    cmd.arg("-o").arg(exe).args(objs);
+    run_tool(&mut cmd)
+}
+
+/// 外部コマンドを実行して終了コードを確かめる
+fn run_tool(cmd: &mut Command) -> Result<()> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let status = cmd.status().with_context(|| format!("Failed to run '{}'", program))?;
+    if !status.success() {
+        bail!("'{}' failed with {}", program, status);
+    }
+    Ok(())
+}
+
+#[test]
+/// 拡張子で入力の種類を決める
+fn test_input_kind() {
+    assert_eq!(InputKind::from_path("foo.c"), InputKind::Source);
+    assert_eq!(InputKind::from_path("-"), InputKind::Source);
+    assert_eq!(InputKind::from_path("dir/foo.s"), InputKind::Asm);
+    assert_eq!(InputKind::from_path("foo.o"), InputKind::Object);
+}
+
+#[test]
+/// 出力ファイルはカレントディレクトリに置く
+fn test_output_path() {
+    assert_eq!(output_path("dir/foo.c", "s"), PathBuf::from("foo.s"));
+    assert_eq!(output_path("-", "o"), PathBuf::from("a.o"));
+}
+
+#[test]
+/// `-o -`と標準入力からの入力はアセンブリを標準出力に書く
+fn test_asm_output() {
+    assert_eq!(AsmOutput::new(Some("-"), "a.c"), AsmOutput::Stdout);
+    assert_eq!(AsmOutput::new(None, "-"), AsmOutput::Stdout);
+    assert_eq!(AsmOutput::new(Some("out.s"), "-"), AsmOutput::File(PathBuf::from("out.s")));
+    assert_eq!(AsmOutput::new(None, "dir/a.c"), AsmOutput::File(PathBuf::from("a.s")));
+}
+
+#[test]
+/// 一時ファイルはdropすると消える
+fn test_temp_files_cleanup() {
+    let mut temps = TempFiles::new();
+    let path = temps.path("s");
+    fs::write(&path, "").unwrap();
+    assert!(path.exists());
+    drop(temps);
+    assert!(!path.exists());
+}
@@ -1,13 +1,27 @@
 use std::collections::HashMap;
 
-use crate::ir::types_ir::VirtualReg;
+use crate::ir::types_ir::{VirtualReg, Width};
+use crate::types::Type;
 
 pub struct Frame {
     pub vreg_to_offset: HashMap<VirtualReg, usize>,
+    /// 変数の領域の大きさ (バイト)
+    vreg_to_size: HashMap<VirtualReg, usize>,
+    /// 使用済みのスタック領域の大きさ
+    size: usize,
 }
 
 impl Frame {
+    /// 全ての変数を8バイトとして配置する
     pub fn from_lvar_map(lvar_map: HashMap<String, VirtualReg>) -> Self {
+        Frame::with_types(lvar_map, &HashMap::new())
+    }
+
+    /// 変数の型の大きさに合わせて配置する
+    /// - 各変数のオフセットはその型の境界に揃える
+    /// - 配列は連続した領域に置き, 先頭の要素が一番低いアドレスになる
+    /// - 型が分からない変数は8バイトとして扱う
+    pub fn with_types(lvar_map: HashMap<String, VirtualReg>, var_types: &HashMap<VirtualReg, Type>) -> Self {
         let mut vec = Vec::new();
         for x in lvar_map {
             vec.push(x);
@@ -15,15 +29,72 @@ impl Frame {
 
         // 昇順にする
         vec.sort_by_key(|(_, vreg)| vreg.id);
-        
+
         // オフセットを計算
         let mut vreg_to_offset = HashMap::new();
-        let mut offset = 8;
+        let mut vreg_to_size = HashMap::new();
+        let mut offset = 0;
         for (_, vreg) in vec {
+            let (size, align) = var_types.get(&vreg).map_or((8, 8), |ty| (ty.size(), ty.align()));
+            offset = (offset + size).next_multiple_of(align);
             vreg_to_offset.entry(vreg).or_insert(offset);
-            offset += 8;
+            vreg_to_size.insert(vreg, size);
         }
-        
-        Frame { vreg_to_offset }
+
+        Frame { vreg_to_offset, vreg_to_size, size: offset }
+    }
+
+    /// スピル用のスロットを確保してそのオフセットを返す
+    /// - ローカル変数の領域の後ろに8バイトずつ積む
+    pub fn alloc_spill_slot(&mut self) -> usize {
+        self.size = (self.size + 8).next_multiple_of(8);
+        self.size
+    }
+
+    /// 変数がスタック上に置かれているか調べる
+    pub fn is_var(&self, vreg: &VirtualReg) -> bool {
+        self.vreg_to_offset.contains_key(vreg)
+    }
+
+    /// 変数を読み書きするときの幅
+    pub fn width(&self, vreg: &VirtualReg) -> Width {
+        Width::from_size(self.vreg_to_size[vreg])
     }
-}
\ No newline at end of file
+
+    /// 16バイト境界に揃えたスタックサイズを返す
+    pub fn stack_size(&self) -> usize {
+        self.size.div_ceil(16) * 16
+    }
+}
+
+#[test]
+/// 変数の大きさに合わせてオフセットを揃える
+fn test_frame_with_types() {
+    let (a, p, b) = (VirtualReg { id: 0 }, VirtualReg { id: 1 }, VirtualReg { id: 2 });
+    let lvar_map = HashMap::from([("a".to_string(), a), ("p".to_string(), p), ("b".to_string(), b)]);
+    let var_types = HashMap::from([(a, Type::Int), (p, Type::Ptr(Box::new(Type::Int))), (b, Type::Int)]);
+    let mut frame = Frame::with_types(lvar_map, &var_types);
+
+    assert_eq!(frame.vreg_to_offset[&a], 4);
+    assert_eq!(frame.vreg_to_offset[&p], 16);
+    assert_eq!(frame.vreg_to_offset[&b], 20);
+    assert_eq!(frame.width(&a), Width::Dword);
+    assert_eq!(frame.width(&p), Width::Qword);
+    // スピル用のスロットは8バイト境界に置く
+    assert_eq!(frame.alloc_spill_slot(), 32);
+    assert_eq!(frame.stack_size(), 32);
+}
+
+#[test]
+/// 配列は連続した領域に置く
+fn test_frame_array() {
+    let (c, a) = (VirtualReg { id: 0 }, VirtualReg { id: 1 });
+    let lvar_map = HashMap::from([("c".to_string(), c), ("a".to_string(), a)]);
+    let var_types = HashMap::from([(c, Type::Char), (a, Type::Array(Box::new(Type::Int), 10))]);
+    let frame = Frame::with_types(lvar_map, &var_types);
+
+    // a[0]が[rbp - 44], a[9]が[rbp - 8]
+    assert_eq!(frame.vreg_to_offset[&c], 1);
+    assert_eq!(frame.vreg_to_offset[&a], 44);
+    assert_eq!(frame.stack_size(), 48);
+}
@@ -1,26 +1,126 @@
 use std::collections::HashMap;
-use crate::{frame::Frame, ir::types_ir::{BinOp, Label, ThreeAddressCode as TAC, VirtualReg}};
+use std::io::{self, Write};
+use crate::{frame::Frame, ir::types_ir::{BinOp, Label, ThreeAddressCode as TAC, VirtualReg, Width}};
+use crate::reg_alloc::register_allocation::Location;
+use crate::sema::GlobalVar;
+use crate::source_map::SourceMap;
+
+/// 64bitレジスタ名を`width`の大きさのレジスタ名に変換する
+fn sized_reg(reg: &str, width: Width) -> String {
+    match (width, reg) {
+        (Width::Qword, _) => reg.to_string(),
+        (Width::Dword, "rax" | "rbx" | "rcx" | "rdx" | "rsi" | "rdi") => format!("e{}", &reg[1..]),
+        (Width::Byte, "rax" | "rbx" | "rcx" | "rdx") => format!("{}l", &reg[1..2]),
+        (Width::Byte, "rsi" | "rdi") => format!("{}l", &reg[1..]),
+        (Width::Dword, "r8" | "r9" | "r10" | "r11" | "r12" | "r13" | "r14" | "r15") => format!("{}d", reg),
+        (Width::Byte, "r8" | "r9" | "r10" | "r11" | "r12" | "r13" | "r14" | "r15") => format!("{}b", reg),
+        _ => unreachable!("no {:?} name for '{}'", width, reg),
+    }
+}
+
+/// グローバル変数の領域を出力する
+/// - 初期値があれば`.data`, 無ければ`.bss`に置く
+/// - `extern`宣言は他のファイルで確保されるので出力しない
+pub fn gen_globals(globals: &[GlobalVar], out: &mut dyn Write) -> io::Result<()> {
+    let (data, bss): (Vec<_>, Vec<_>) = globals.iter()
+        .filter(|g| !g.is_extern)
+        .partition(|g| g.init.is_some());
+    if !data.is_empty() {
+        writeln!(out, ".data")?;
+    }
+    for global in data {
+        writeln!(out, ".globl {}", global.name)?;
+        writeln!(out, ".align {}", global.ty.align())?;
+        writeln!(out, "{}:", global.name)?;
+        let init = global.init.unwrap_or(0);
+        match Width::from_size(global.ty.size()) {
+            Width::Byte => writeln!(out, "  .byte {}", init as i8)?,
+            Width::Dword => writeln!(out, "  .long {}", init)?,
+            Width::Qword => writeln!(out, "  .quad {}", init)?,
+        }
+    }
+    if !bss.is_empty() {
+        writeln!(out, ".bss")?;
+    }
+    for global in bss {
+        writeln!(out, ".globl {}", global.name)?;
+        writeln!(out, ".align {}", global.ty.align())?;
+        writeln!(out, "{}:", global.name)?;
+        writeln!(out, "  .zero {}", global.ty.size())?;
+    }
+    Ok(())
+}
+
+/// 文字列リテラルのラベル
+/// - `.L`で始まるラベルはオブジェクトファイルのシンボルに残らない
+fn str_label(id: usize) -> String {
+    format!(".LC{id}")
+}
+
+/// 文字列リテラルを`.string`の中に書ける形にする
+/// - 表示できない文字は3桁の8進数にする
+fn escape_str(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &b in bytes {
+        match b {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    escaped
+}
+
+/// 文字列リテラルを`.rodata`に出力する
+/// - `strings`の添字がラベルの番号になる
+/// - 終端の`\0`は`.string`が付ける
+pub fn gen_strings(strings: &[Vec<u8>], out: &mut dyn Write) -> io::Result<()> {
+    if !strings.is_empty() {
+        writeln!(out, ".section .rodata")?;
+    }
+    for (id, bytes) in strings.iter().enumerate() {
+        writeln!(out, "{}:", str_label(id))?;
+        writeln!(out, "  .string \"{}\"", escape_str(bytes))?;
+    }
+    Ok(())
+}
 
 pub struct Generator<'a> {
     regs: Vec<&'a str>,
     code: Vec<TAC>,
     pub frame: Frame,
+    out: &'a mut dyn Write,
+    /// `Loc`を`.loc`にするときに使う (無ければ`Loc`は出力しない)
+    source_map: Option<&'a SourceMap<'a>>,
 }
 
 impl<'a> Generator<'a> {
-    pub fn new(regs: Vec<&'a str>, code: Vec<TAC>, frame: Frame) -> Generator<'a> {
+    /// - アセンブリは`out`に書き出す
+    pub fn new(regs: Vec<&'a str>, code: Vec<TAC>, frame: Frame, out: &'a mut dyn Write) -> Generator<'a> {
         Generator {
             regs,
             code,
             frame,
+            out,
+            source_map: None,
         }
     }
+
+    /// 行番号のデバッグ情報 (`.loc`) を出力する
+    /// - `.file 1`は呼び出し側で出力しておく
+    pub fn set_source_map(&mut self, source_map: &'a SourceMap<'a>) {
+        self.source_map = Some(source_map);
+    }
     
     /// アセンブリ生成はここから
-    pub fn gen_fn(&self, vreg_to_reg: HashMap<VirtualReg, usize>) {
-        for instr in &self.code {
-            self.generate(&vreg_to_reg, instr);
+    pub fn gen_fn(&mut self, vreg_to_loc: HashMap<VirtualReg, Location>) -> io::Result<()> {
+        let code = std::mem::take(&mut self.code);
+        for instr in &code {
+            self.generate(&vreg_to_loc, instr)?;
         }
+        self.code = code;
+        Ok(())
     }
     
     fn label_to_string(&self, label: Label) -> String {
@@ -37,13 +137,16 @@ impl<'a> Generator<'a> {
         }
     }
     
-    /// 仮想レジスタを受け取って実際のレジスタ名をStringで返す
-    fn vreg_to_string(&self, vreg: &VirtualReg, vreg_to_reg: &HashMap<VirtualReg, usize>) -> String {
-        let msg = format!("Missing vreg key '{:?}' in 'vreg_to_reg'", vreg);
-        let reg_idx = vreg_to_reg.get(vreg).expect(&msg);
+    /// 仮想レジスタを受け取って割り当て先を返す
+    fn get_location(&self, vreg: &VirtualReg, vreg_to_loc: &HashMap<VirtualReg, Location>) -> Location {
+        let msg = format!("Missing vreg key '{:?}' in 'vreg_to_loc'", vreg);
+        *vreg_to_loc.get(vreg).expect(&msg)
+    }
 
-        let msg = format!("vreg_to_reg returned '{:?}' which is out of range", reg_idx);
-        self.regs.get(*reg_idx).expect(&msg).to_string()
+    /// 物理レジスタの番号をレジスタ名に変換する
+    fn reg_name(&self, reg_idx: usize) -> String {
+        let msg = format!("vreg_to_loc returned '{:?}' which is out of range", reg_idx);
+        self.regs.get(reg_idx).expect(&msg).to_string()
     }
     
     /// 仮想レジスタを受け取り, 対応する変数のオフセットを返す.
@@ -54,235 +157,334 @@ impl<'a> Generator<'a> {
         let offset = self.frame.vreg_to_offset.get(vreg).expect(&msg);
         *offset
     }
+
+    /// メモリ`mem`から`width`の値を読み, 64bitに符号拡張して`dest`に入れる
+    fn load_mem(&mut self, dest: &str, mem: &str, width: Width) -> io::Result<()> {
+        match width {
+            Width::Byte => writeln!(self.out, "  movsx {}, BYTE PTR {}", dest, mem),
+            Width::Dword => writeln!(self.out, "  movsxd {}, DWORD PTR {}", dest, mem),
+            Width::Qword => writeln!(self.out, "  mov {}, {}", dest, mem),
+        }
+    }
+
+    /// レジスタ`reg`の下位`width`を64bitに符号拡張する
+    /// - 64bitの値 (ポインタ) はそのまま
+    fn sign_extend(&mut self, reg: &str, width: Width) -> io::Result<()> {
+        match width {
+            Width::Byte => writeln!(self.out, "  movsx {}, {}", reg, sized_reg(reg, width)),
+            Width::Dword => writeln!(self.out, "  movsxd {}, {}", reg, sized_reg(reg, width)),
+            Width::Qword => Ok(()),
+        }
+    }
+
+    /// レジスタ`src`の下位`width`をメモリ`mem`に書き込む
+    fn store_mem(&mut self, mem: &str, src: &str, width: Width) -> io::Result<()> {
+        match width {
+            Width::Byte => writeln!(self.out, "  mov BYTE PTR {}, {}", mem, sized_reg(src, width)),
+            Width::Dword => writeln!(self.out, "  mov DWORD PTR {}, {}", mem, sized_reg(src, width)),
+            Width::Qword => writeln!(self.out, "  mov {}, {}", mem, src),
+        }
+    }
+
+    /// 仮想レジスタの値が入っているレジスタ名を返す
+    /// - 変数やスピルされた値は作業用レジスタ`scratch`にロードする
+    fn load(&mut self, vreg: &VirtualReg, vreg_to_loc: &HashMap<VirtualReg, Location>, scratch: &str) -> io::Result<String> {
+        if let Some(&offset) = self.frame.vreg_to_offset.get(vreg) {
+            // int, charの変数は符号拡張して読む
+            let width = self.frame.width(vreg);
+            self.load_mem(scratch, &format!("[rbp - {}]", offset), width)?;
+            return Ok(scratch.to_string());
+        }
+        match self.get_location(vreg, vreg_to_loc) {
+            Location::Reg(idx) => Ok(self.reg_name(idx)),
+            Location::Spill(offset) => {
+                writeln!(self.out, "  mov {}, [rbp - {}]", scratch, offset)?;
+                Ok(scratch.to_string())
+            }
+        }
+    }
+
+    /// 結果を書き込むレジスタ名を返す
+    /// - 変数やスピルされた値は作業用レジスタ`scratch`で計算する
+    /// - 計算後は`write_back`を呼ぶ
+    fn dest_reg(&self, vreg: &VirtualReg, vreg_to_loc: &HashMap<VirtualReg, Location>, scratch: &str) -> String {
+        if self.frame.is_var(vreg) {
+            return scratch.to_string();
+        }
+        match self.get_location(vreg, vreg_to_loc) {
+            Location::Reg(idx) => self.reg_name(idx),
+            Location::Spill(_) => scratch.to_string(),
+        }
+    }
+
+    /// レジスタ`reg`の値を仮想レジスタの割り当て先に書き込む
+    fn write_back(&mut self, vreg: &VirtualReg, vreg_to_loc: &HashMap<VirtualReg, Location>, reg: &str) -> io::Result<()> {
+        if let Some(&offset) = self.frame.vreg_to_offset.get(vreg) {
+            let width = self.frame.width(vreg);
+            self.store_mem(&format!("[rbp - {}]", offset), reg, width)?;
+            return Ok(());
+        }
+        match self.get_location(vreg, vreg_to_loc) {
+            Location::Reg(idx) => {
+                let dest = self.reg_name(idx);
+                if dest != reg {
+                    writeln!(self.out, "  mov {}, {}", dest, reg)?;
+                }
+            }
+            Location::Spill(offset) => {
+                writeln!(self.out, "  mov [rbp - {}], {}", offset, reg)?;
+            }
+        }
+        Ok(())
+    }
     
-    fn generate(&self, vreg_to_reg: &HashMap<VirtualReg, usize>, instr: &TAC) {
+    fn generate(&mut self, vreg_to_loc: &HashMap<VirtualReg, Location>, instr: &TAC) -> io::Result<()> {
+        // 変数やスピルされた値を扱うための作業用レジスタ
+        // 割り当て対象のレジスタとは重ならない
+        let (scratch0, scratch1) = ("r10", "r11");
         match instr {
             TAC::LoadImm { dest, value} => {
-                let dest_reg_idx = vreg_to_reg.get(dest).unwrap();
-                println!("  mov {}, {}", self.regs[*dest_reg_idx], value);            
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                writeln!(self.out, "  mov {}, {}", dest_reg, value)?;
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
             }
-            TAC::BinOpCode { dest, left, op, right } => {
-                let left_reg = self.vreg_to_string(left, vreg_to_reg);
-                let right_reg = self.vreg_to_string(right, vreg_to_reg);
-                let dest_reg = self.vreg_to_string(dest, vreg_to_reg);
+            TAC::BinOpCode { dest, left, op, right, width } => {
                 // 変数のときはレジスタに最新の値をロードする
-                if let Some(offset) = self.frame.vreg_to_offset.get(left) {
-                    println!("  mov {}, [rbp - {}]", left_reg, offset);
-                }
-                if let Some(offset) = self.frame.vreg_to_offset.get(right) {
-                    println!("  mov {}, [rbp - {}]", right_reg, offset);
-                }
+                let left_reg = self.load(left, vreg_to_loc, scratch0)?;
+                let right_reg = self.load(right, vreg_to_loc, scratch1)?;
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                // intの演算は32bitのレジスタで行う
+                let sized = |reg: &str| sized_reg(reg, *width);
+                let (left_op, right_op, dest_op) = (sized(&left_reg), sized(&right_reg), sized(&dest_reg));
                 match op {
-                    BinOp::Add => {
-                        if dest_reg == right_reg {
-                            let tmp = "rbx";
-                            println!("  mov {}, {}", tmp, left_reg);
-                            println!("  add {}, {}", tmp, right_reg);
-                            println!("  mov {}, {}", dest_reg, tmp);
-                        } else {
-                            println!("  mov {}, {}", dest_reg, left_reg);
-                            println!("  add {}, {}", dest_reg, right_reg);
-                        }
-                    }
-                    BinOp::Sub => {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                        let mnemonic = match op {
+                            BinOp::Add => "add",
+                            BinOp::Sub => "sub",
+                            _ => "imul",
+                        };
                         if dest_reg == right_reg {
-                            let tmp = "rbx";
-                            println!("  mov {}, {}", tmp, left_reg);
-                            println!("  sub {}, {}", tmp, right_reg);
-                            println!("  mov {}, {}", dest_reg, tmp);
+                            // rbxなどはcallee-savedなので壊さないようにraxを使う
+                            let tmp = sized("rax");
+                            writeln!(self.out, "  mov {}, {}", tmp, left_op)?;
+                            writeln!(self.out, "  {} {}, {}", mnemonic, tmp, right_op)?;
+                            writeln!(self.out, "  mov {}, {}", dest_op, tmp)?;
                         } else {
-                            println!("  mov {}, {}", dest_reg, left_reg);
-                            println!("  sub {}, {}", dest_reg, right_reg);
-                        }
-                    }
-                    BinOp::Mul => {
-                        if dest_reg == right_reg {
-                            let tmp = "rbx";
-                            println!("  mov {}, {}", tmp, left_reg);
-                            println!("  imul {}, {}", tmp, right_reg);
-                            println!("  mov {}, {}", dest_reg, tmp);
-                        } else {
-                            println!("  mov {}, {}", dest_reg, left_reg);
-                            println!("  imul {}, {}", dest_reg, right_reg);
+                            writeln!(self.out, "  mov {}, {}", dest_op, left_op)?;
+                            writeln!(self.out, "  {} {}, {}", mnemonic, dest_op, right_op)?;
                         }
                     }
                     BinOp::Div => {
                         // rdxの値を避難させる
                         // いつでも符号拡張で壊れる可能性があるため常に行う
-                        let tmp = "rbx";
-                        println!("  mov {}, rdx", tmp);
+                        writeln!(self.out, "  push rdx")?;
+                        // 割る数がrdxにあるときは作業用レジスタに写しておく
+                        // (このときright_regはscratch1ではない)
+                        let divisor = if right_reg == "rdx" {
+                            writeln!(self.out, "  mov {}, rdx", scratch1)?;
+                            sized(scratch1)
+                        } else {
+                            right_op
+                        };
 
                         // raxの値が割られる数
-                        println!("  mov rax, {}", left_reg);
-                        // raxを128bitに拡張してこれだけ使う
-                        println!("  cqo");
-                        if right_reg == "rdx".to_string() {
-                            println!("  idiv {}", tmp);
-                        } else {
-                            println!("  idiv {}", right_reg);
+                        writeln!(self.out, "  mov {}, {}", sized("rax"), left_op)?;
+                        // raxを符号拡張してrdx:raxにする
+                        match width {
+                            Width::Byte => unreachable!("byte arithmetic is done as int"),
+                            Width::Dword => writeln!(self.out, "  cdq")?,
+                            Width::Qword => writeln!(self.out, "  cqo")?,
                         }
-                        // raxの値が商になる
-                        println!("  mov {}, rax", dest_reg);
-                        
+                        writeln!(self.out, "  idiv {}", divisor)?;
                         // rdxの値を復活させる
-                        println!("  mov rdx, {}", tmp);
-                    }
-                    BinOp::Le => {
-                        println!("  cmp {}, {}", left_reg, right_reg);
-                        println!("  setle al");
-                        println!("  movzb {}, al", dest_reg);
-                    }
-                    BinOp::Lt => {
-                        println!("  cmp {}, {}", left_reg, right_reg);
-                        println!("  setl al");
-                        println!("  movzb {}, al", dest_reg);
-                    }
-                    BinOp::Eq => {
-                        println!("  cmp {}, {}", left_reg, right_reg);
-                        println!("  sete al");
-                        println!("  movzb {}, al", dest_reg);
+                        // 書き込み先がrdxの場合に上書きしないよう先に戻す
+                        writeln!(self.out, "  pop rdx")?;
+
+                        // raxの値が商になる
+                        writeln!(self.out, "  mov {}, {}", dest_op, sized("rax"))?;
                     }
-                    BinOp::Ne => {
-                        println!("  cmp {}, {}", left_reg, right_reg);
-                        println!("  setne al");
-                        println!("  movzb {}, al", dest_reg);
+                    BinOp::Le | BinOp::Lt | BinOp::Eq | BinOp::Ne => {
+                        let set = match op {
+                            BinOp::Le => "setle",
+                            BinOp::Lt => "setl",
+                            BinOp::Eq => "sete",
+                            _ => "setne",
+                        };
+                        writeln!(self.out, "  cmp {}, {}", left_op, right_op)?;
+                        writeln!(self.out, "  {} al", set)?;
+                        writeln!(self.out, "  movzb {}, al", dest_reg)?;
                     }
                 }
+                // 32bitの結果は64bitに符号拡張しておく
+                let is_arith = matches!(op, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div);
+                if *width == Width::Dword && is_arith {
+                    writeln!(self.out, "  movsxd {}, {}", dest_reg, dest_op)?;
+                }
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
             }
             TAC::Assign { dest, src } => {
-                let src_reg = self.vreg_to_string(src, vreg_to_reg);
-                let offset = self.get_offset(dest);
-                println!("  mov [rbp - {}], {}", offset, src_reg);
+                let src_reg = self.load(src, vreg_to_loc, scratch0)?;
+                self.write_back(dest, vreg_to_loc, &src_reg)?;
             }
             TAC::EvalVar { .. } => {
-                // let offset = self.get_offset(var);
-                // let var_reg = self.vreg_to_string(var, vreg_to_reg);
-                // println!("  mov {}, [rbp - {}]", var_reg, offset);
+                // 変数は使用する命令の側でスタックから読み込む
             }
             TAC::AddrOf { addr, var } => {
                 // 参照
                 let offset = self.get_offset(var);
-                let addr_reg = self.vreg_to_string(addr, vreg_to_reg);
-                println!("  lea {}, [rbp - {}]", addr_reg, offset);
+                let addr_reg = self.dest_reg(addr, vreg_to_loc, scratch0);
+                writeln!(self.out, "  lea {}, [rbp - {}]", addr_reg, offset)?;
+                self.write_back(addr, vreg_to_loc, &addr_reg)?;
             }
-            TAC::LoadVar { value: dest, addr } => {
+            TAC::LoadVar { value: dest, addr, width } => {
                 // 参照外し
-                let dest_reg = self.vreg_to_string(dest, vreg_to_reg);
-                let addr_reg = self.vreg_to_string(addr, vreg_to_reg);
                 // 変数のときは最新の値をロードしてから
-                // 変数ではなくて参照外しが続いているケースがあるため分岐する
-                if let Some(offset) = self.frame.vreg_to_offset.get(addr) {
-                    println!("  mov {}, [rbp - {}]", addr_reg, offset);
-                }
-                println!("  mov {}, [{}]", dest_reg, addr_reg);
+                let addr_reg = self.load(addr, vreg_to_loc, scratch1)?;
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                self.load_mem(&dest_reg, &format!("[{}]", addr_reg), *width)?;
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
             }
-            TAC::Store { addr, src } => {
-                let addr_reg = self.vreg_to_string(addr, vreg_to_reg);
-                let src_reg = self.vreg_to_string(src, vreg_to_reg);
-                if let Some(offset) = self.frame.vreg_to_offset.get(addr) {
-                    println!("  mov {}, [rbp - {}]", addr_reg, offset);
-                }
-                println!("  mov [{}], {}", addr_reg, src_reg);
+            TAC::Store { addr, src, width } => {
+                let addr_reg = self.load(addr, vreg_to_loc, scratch0)?;
+                let src_reg = self.load(src, vreg_to_loc, scratch1)?;
+                self.store_mem(&format!("[{}]", addr_reg), &src_reg, *width)?;
+            }
+            TAC::GlobalAddr { dest, name } => {
+                // グローバル変数はRIP相対で参照する
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                writeln!(self.out, "  lea {}, [rip + {}]", dest_reg, name)?;
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
+            }
+            TAC::LoadGlobal { dest, name, width } => {
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                self.load_mem(&dest_reg, &format!("[rip + {}]", name), *width)?;
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
+            }
+            TAC::StoreGlobal { name, src, width } => {
+                let src_reg = self.load(src, vreg_to_loc, scratch0)?;
+                self.store_mem(&format!("[rip + {}]", name), &src_reg, *width)?;
+            }
+            TAC::StrAddr { dest, id } => {
+                let dest_reg = self.dest_reg(dest, vreg_to_loc, scratch0);
+                writeln!(self.out, "  lea {}, [rip + {}]", dest_reg, str_label(*id))?;
+                self.write_back(dest, vreg_to_loc, &dest_reg)?;
             }
             TAC::Return { src } => {
-                let src_reg = self.vreg_to_string(src, vreg_to_reg);
                 // 変数のときはレジスタに最新の値をロードする
-                if let Some(offset) = self.frame.vreg_to_offset.get(src) {
-                    println!("  mov {}, [rbp - {}]", src_reg, offset);
-                }
-                println!("  mov rax, {}", src_reg);
+                let src_reg = self.load(src, vreg_to_loc, scratch0)?;
+                writeln!(self.out, "  mov rax, {}", src_reg)?;
                 // 関数エピローグ
-                println!("  mov rsp, rbp");
-                println!("  pop rbp");
-                println!("  ret");
+                writeln!(self.out, "  mov rsp, rbp")?;
+                writeln!(self.out, "  pop rbp")?;
+                writeln!(self.out, "  ret")?;
             }
             TAC::IfFalse { cond, label } => {
-                let cond_reg = self.vreg_to_string(cond, vreg_to_reg);
+                let cond_reg = self.load(cond, vreg_to_loc, scratch0)?;
                 let real_label = self.label_to_string(label.clone());
-                println!("  cmp {}, 0", cond_reg);
-                println!("  je {}", real_label);
+                writeln!(self.out, "  cmp {}, 0", cond_reg)?;
+                writeln!(self.out, "  je {}", real_label)?;
             }
             TAC::GoTo { label } => {
                 let real_label = self.label_to_string(label.clone());
-                println!("  jmp {}", real_label);
+                writeln!(self.out, "  jmp {}", real_label)?;
             }
             TAC::Label { label } => {
                 let real_label = self.label_to_string(label.clone());
-                println!("{}:", real_label);
+                writeln!(self.out, "{}:", real_label)?;
             }
-            TAC::Call { fn_name, args, ret_reg } => {
-                let mut args_reg = Vec::new();
-                for arg in args {
-                    args_reg.push(self.vreg_to_string(arg, vreg_to_reg));
-                }
-
+            TAC::Call { fn_name, args, ret_reg, width } => {
                 // 現在のレジスタを待避
                 let regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
                 for r in &regs {
-                    println!("  push {}", r);
+                    writeln!(self.out, "  push {}", r)?;
                 }
 
-                // 衝突防止のため一時レジスタに代入する
-                let save = ["rbx", "r12", "r13", "r14", "r15"];
-                for (i, arg) in args_reg.iter().enumerate() {
-                    if let Some(s) = save.get(i) {
-                        println!("  mov {}, {}", s, arg);
-                    } else {
-                        println!("  push {}", arg);
-                    }
+                // 7個目以降の引数はスタックに右から積む
+                // call の直前でrspが16の倍数になるように, 奇数個なら8バイト空けておく
+                // (フレームと待避したレジスタの大きさは16の倍数)
+                let (reg_args, stack_args) = args.split_at(args.len().min(regs.len()));
+                let padding = if stack_args.len() % 2 == 1 { 8 } else { 0 };
+                if padding != 0 {
+                    writeln!(self.out, "  sub rsp, {}", padding)?;
                 }
-                
-                // 一時レジスタを介して引数に渡す
-                for i in 0..args.len() {
-                    let dest = regs.get(i).expect("too many args are given");
-                    if let Some(s) = save.get(i) {
-                        println!("  mov {}, {}", dest, s);
-                    } else {
-                        println!("  pop {}", dest);
-                    }
+                for arg in stack_args.iter().rev() {
+                    let arg = self.load(arg, vreg_to_loc, scratch0)?;
+                    writeln!(self.out, "  push {}", arg)?;
                 }
 
-                println!("  call {}", fn_name);
+                // 引数のレジスタが他の引数の値を持っていることがあるため
+                // 一度スタックに積んでから取り出す
+                for arg in reg_args {
+                    let arg = self.load(arg, vreg_to_loc, scratch0)?;
+                    writeln!(self.out, "  push {}", arg)?;
+                }
+                for dest in regs[..reg_args.len()].iter().rev() {
+                    writeln!(self.out, "  pop {}", dest)?;
+                }
+
+                // 可変長引数の関数のためにベクタレジスタで渡す引数の数 (al) を0にする
+                writeln!(self.out, "  mov eax, 0")?;
+                writeln!(self.out, "  call {}", fn_name)?;
+
+                // スタックに積んだ引数を捨てる
+                let stack_size = stack_args.len() * 8 + padding;
+                if stack_size != 0 {
+                    writeln!(self.out, "  add rsp, {}", stack_size)?;
+                }
                 
                 // レジスタを復活させる
                 for r in regs.iter().rev() {
-                    println!("  pop {}", r);
+                    writeln!(self.out, "  pop {}", r)?;
                 }
 
-                let ret_val_reg = self.vreg_to_string(ret_reg, vreg_to_reg);
-                println!("  mov {}, rax", ret_val_reg);
+                // 戻り値の型の幅から符号拡張する
+                self.sign_extend("rax", *width)?;
+                self.write_back(ret_reg, vreg_to_loc, "rax")?;
             }
             TAC::Fn { fn_name, params } => {
-                // 最大オフセットを使用してスタックサイズを計算
-                let mut offset_max: usize = 0; 
-                for (_, offset) in self.frame.vreg_to_offset.clone() {
-                    if offset > offset_max {
-                        offset_max = offset;
-                    }
-                }
-                let stack_size = ((offset_max + 15) / 16) * 16;
+                // 変数とスピル領域を合わせたスタックサイズ
+                let stack_size = self.frame.stack_size();
                 
                 // 関数プロローグ
-                println!("{}:", fn_name);
-                println!("  push rbp");
-                println!("  mov rbp, rsp");
-                println!("  sub rsp, {}", stack_size);
+                // 他のファイルから呼べるように全ての関数を公開する
+                writeln!(self.out, ".globl {}", fn_name)?;
+                writeln!(self.out, "{}:", fn_name)?;
+                writeln!(self.out, "  push rbp")?;
+                writeln!(self.out, "  mov rbp, rsp")?;
+                writeln!(self.out, "  sub rsp, {}", stack_size)?;
 
                 // 引数の受け渡し(Linux)
                 // OSによってルールが異なることに注意
                 // 代入前に値が壊れてしまうことがあるためスタックに一時保存
-                let recv_regs = vec!["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-                for i in 0..params.len() {
-                    println!("  push {}", recv_regs.get(i).expect("too many args"));
+                let recv_regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+                let (reg_params, stack_params) = params.split_at(params.len().min(recv_regs.len()));
+                // 引数の型の幅から符号拡張しておく
+                for (reg, param) in recv_regs.iter().zip(reg_params) {
+                    self.sign_extend(reg, param.width)?;
+                    writeln!(self.out, "  push {}", reg)?;
+                }
+                for param in reg_params.iter().rev() {
+                    writeln!(self.out, "  pop {}", scratch0)?;
+                    self.write_back(&param.dest, vreg_to_loc, scratch0)?;
+                }
+                // 7個目以降の引数は呼び出し元のスタックにある
+                // (戻りアドレスと保存したrbpの上)
+                for (k, param) in stack_params.iter().enumerate() {
+                    self.load_mem(scratch0, &format!("[rbp + {}]", 16 + 8 * k), param.width)?;
+                    self.write_back(&param.dest, vreg_to_loc, scratch0)?;
                 }
-                for param in params.iter().rev() {
-                    let dest = self.vreg_to_string(&param.dest, vreg_to_reg);
-                    let offset = self.get_offset(&param.dest);
-                    println!("  pop {}", dest);
-                    println!("  mov [rbp - {}], {}", offset, dest);
+            }
+            TAC::Phi { .. } => {
+                unreachable!("phi should be removed by ssa::destruct before code generation");
+            }
+            TAC::Loc { span } => {
+                if let Some(map) = self.source_map {
+                    let (line, col) = map.line_col(span.start);
+                    writeln!(self.out, "  .loc 1 {} {}", line, col)?;
                 }
             }
             // ワイルドカードを使わない
         }
+        Ok(())
     }
 }
@@ -1,2 +1,5 @@
+pub mod cfg;
+pub mod dominance;
 pub mod gen_ir;
+pub mod ssa;
 pub mod types_ir;
\ No newline at end of file
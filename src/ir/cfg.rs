@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::types_ir::{Label, ThreeAddressCode as TAC, VirtualReg};
+
+/// 基本ブロック
+/// - 先頭以外に`Label`を含まない
+/// - 末尾以外に`GoTo`, `IfFalse`, `Return`を含まない
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub code: Vec<TAC>,
+    pub preds: Vec<usize>,
+    pub succs: Vec<usize>,
+}
+
+impl BasicBlock {
+    fn new() -> Self {
+        BasicBlock { code: Vec::new(), preds: Vec::new(), succs: Vec::new() }
+    }
+
+    /// 先頭のラベルを返す
+    pub fn label(&self) -> Option<&Label> {
+        match self.code.first() {
+            Some(TAC::Label { label }) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// 末尾から次のブロックへ落ちるか調べる
+    fn falls_through(&self) -> bool {
+        !matches!(self.code.last(), Some(TAC::GoTo { .. }) | Some(TAC::Return { .. }))
+    }
+}
+
+/// 関数ひとつ分の制御フローグラフ
+/// - `blocks`の並びは元の命令列の順序を保つ
+/// - 先頭のブロックが入口
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// 命令列を基本ブロックに分割して辺を張る
+    pub fn new(code: &[TAC]) -> Self {
+        let mut blocks = Vec::new();
+        let mut current = BasicBlock::new();
+
+        for tac in code {
+            match tac {
+                TAC::Label { .. } => {
+                    // ラベルは新しいブロックの先頭になる
+                    if !current.code.is_empty() {
+                        blocks.push(current);
+                        current = BasicBlock::new();
+                    }
+                    current.code.push(tac.clone());
+                }
+                TAC::GoTo { .. } | TAC::IfFalse { .. } | TAC::Return { .. } => {
+                    // 分岐はブロックの末尾になる
+                    current.code.push(tac.clone());
+                    blocks.push(current);
+                    current = BasicBlock::new();
+                }
+                _ => {
+                    current.code.push(tac.clone());
+                }
+            }
+        }
+        if !current.code.is_empty() {
+            blocks.push(current);
+        }
+
+        let mut cfg = Cfg { blocks };
+        cfg.compute_edges();
+        cfg
+    }
+
+    /// 各ブロックの先行・後続を計算し直す
+    /// - ブロックの中身を書き換えたあとに呼ぶ
+    pub fn compute_edges(&mut self) {
+        let mut label_to_block: HashMap<Label, usize> = HashMap::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if let Some(label) = block.label() {
+                label_to_block.insert(label.clone(), i);
+            }
+        }
+
+        let jump_to = |label: &Label| -> usize {
+            let msg = format!("Missing label '{:?}' in function", label);
+            *label_to_block.get(label).expect(&msg)
+        };
+
+        let len = self.blocks.len();
+        let mut succs = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut s = Vec::new();
+            if block.falls_through() && i + 1 < len {
+                s.push(i + 1);
+            }
+            match block.code.last() {
+                Some(TAC::GoTo { label }) | Some(TAC::IfFalse { label, .. }) => {
+                    let target = jump_to(label);
+                    if !s.contains(&target) {
+                        s.push(target);
+                    }
+                }
+                _ => (),
+            }
+            succs.push(s);
+        }
+
+        for block in &mut self.blocks {
+            block.preds.clear();
+        }
+        for (i, s) in succs.iter().enumerate() {
+            for &succ in s {
+                self.blocks[succ].preds.push(i);
+            }
+        }
+        for (block, s) in self.blocks.iter_mut().zip(succs) {
+            block.succs = s;
+        }
+
+        // 辺が無くなった先行ブロックからのφ関数の引数を取り除く
+        for block in &mut self.blocks {
+            let preds = &block.preds;
+            for tac in &mut block.code {
+                if let TAC::Phi { args, .. } = tac {
+                    args.retain(|(pred, _)| preds.contains(pred));
+                }
+            }
+        }
+    }
+
+    /// 入口から到達できないブロックを取り除く
+    /// - φ関数の引数のブロック番号も付け直す
+    pub fn remove_unreachable(&mut self) {
+        let reachable = self.reachable();
+        let mut new_idx = Vec::new();
+        let mut count = 0;
+        for r in &reachable {
+            if *r {
+                new_idx.push(Some(count));
+                count += 1;
+            } else {
+                new_idx.push(None);
+            }
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for (block, r) in blocks.into_iter().zip(reachable) {
+            if r {
+                self.blocks.push(block);
+            }
+        }
+        for block in &mut self.blocks {
+            for tac in &mut block.code {
+                if let TAC::Phi { args, .. } = tac {
+                    args.retain(|(pred, _)| new_idx[*pred].is_some());
+                    for (pred, _) in args.iter_mut() {
+                        *pred = new_idx[*pred].unwrap();
+                    }
+                }
+            }
+        }
+        self.compute_edges();
+    }
+
+    /// まだ使われていない仮想レジスタの番号を返す
+    /// - 最適化で新しい仮想レジスタを作るときに使う
+    pub fn next_vreg_id(&self) -> usize {
+        self.blocks.iter()
+            .flat_map(|b| b.code.iter())
+            .flat_map(|tac| tac.get_def_regs().into_iter().chain(tac.get_use_regs()))
+            .map(|r| r.id + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// スタック上に置かれる変数を集める
+    /// - `EvalVar`で読まれるか`AddrOf`でアドレスを取られている仮想レジスタ
+    /// - 値がメモリ越しに読み書きされることがあるため最適化では特別に扱う
+    pub fn stack_vars(&self) -> HashSet<VirtualReg> {
+        let mut vars = HashSet::new();
+        for tac in self.blocks.iter().flat_map(|b| b.code.iter()) {
+            match tac {
+                TAC::EvalVar { dest, .. } => {
+                    vars.insert(*dest);
+                }
+                TAC::AddrOf { var, .. } => {
+                    vars.insert(*var);
+                }
+                _ => (),
+            }
+        }
+        vars
+    }
+
+    /// 入口から到達できるブロックを調べる
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = Vec::new();
+        if !self.blocks.is_empty() {
+            stack.push(0);
+        }
+        while let Some(b) = stack.pop() {
+            if visited[b] {
+                continue;
+            }
+            visited[b] = true;
+            stack.extend(self.blocks[b].succs.iter().copied());
+        }
+        visited
+    }
+
+    /// 入口から辿った逆後順 (reverse post order) を返す
+    /// - 前向きのデータフロー解析で使う
+    pub fn reverse_post_order(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+
+        // 再帰を避けるため (ブロック, 次に見る後続の番号) をスタックに積む
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, i)) = stack.pop() {
+            if let Some(&succ) = self.blocks[b].succs.get(i) {
+                stack.push((b, i + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(b);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// 命令列に戻す
+    pub fn to_code(&self) -> Vec<TAC> {
+        self.blocks.iter().flat_map(|b| b.code.iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+fn if_else_ir() -> Vec<TAC> {
+    use crate::ir::types_ir::VirtualReg;
+
+    // if (v0) v1 = 1; else v1 = 2; return v1;
+    vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::IfFalse { cond: VirtualReg { id: 0 }, label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        TAC::GoTo { label: Label::Lend(1) },
+        TAC::Label { label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 2 },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ]
+}
+
+#[test]
+/// if-elseはひし形のグラフになる
+fn test_cfg_if_else() {
+    let cfg = Cfg::new(&if_else_ir());
+
+    assert_eq!(cfg.blocks.len(), 4);
+    let succs: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.succs.clone()).collect();
+    let preds: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.preds.clone()).collect();
+    assert_eq!(succs, vec![vec![1, 2], vec![3], vec![3], vec![]]);
+    assert_eq!(preds, vec![vec![], vec![0], vec![0], vec![1, 2]]);
+    assert_eq!(cfg.blocks[3].label(), Some(&Label::Lend(1)));
+}
+
+#[test]
+/// ループは後方への辺を持つ
+fn test_cfg_loop() {
+    use crate::ir::types_ir::VirtualReg;
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::IfFalse { cond: VirtualReg { id: 0 }, label: Label::Lend(1) },
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 0 } },
+    ];
+    let cfg = Cfg::new(&ir);
+
+    let succs: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.succs.clone()).collect();
+    assert_eq!(succs, vec![vec![1], vec![2, 3], vec![1], vec![]]);
+    assert_eq!(cfg.blocks[1].preds, vec![0, 2]);
+    assert_eq!(cfg.reverse_post_order(), vec![0, 1, 3, 2]);
+}
+
+#[test]
+/// returnの後ろの命令は到達できないブロックになる
+fn test_cfg_unreachable_after_return() {
+    use crate::ir::types_ir::VirtualReg;
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::Return { src: VirtualReg { id: 0 } },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 2 },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ];
+    let cfg = Cfg::new(&ir);
+
+    assert_eq!(cfg.blocks.len(), 2);
+    assert!(cfg.blocks[1].preds.is_empty());
+    assert_eq!(cfg.reachable(), vec![true, false]);
+}
+
+#[test]
+/// 命令列に戻すと元に戻る
+fn test_cfg_to_code() {
+    let ir = if_else_ir();
+    let cfg = Cfg::new(&ir);
+    assert_eq!(cfg.to_code(), ir);
+}
@@ -0,0 +1,142 @@
+use crate::ir::cfg::Cfg;
+
+/// 支配木
+/// - Cooper, Harvey, Kennedy の反復アルゴリズムで求める
+/// - 入口から到達できないブロックの`idom`は`None`
+#[derive(Debug)]
+pub struct DomTree {
+    pub idom: Vec<Option<usize>>,
+    pub children: Vec<Vec<usize>>,
+}
+
+impl DomTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let len = cfg.blocks.len();
+        let rpo = cfg.reverse_post_order();
+        let mut rpo_idx = vec![usize::MAX; len];
+        for (i, b) in rpo.iter().enumerate() {
+            rpo_idx[*b] = i;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; len];
+        if len == 0 {
+            return DomTree { idom, children: Vec::new() };
+        }
+        idom[0] = Some(0);
+
+        // 共通の支配ブロックまで辿る
+        let intersect = |idom: &Vec<Option<usize>>, mut a: usize, mut b: usize| -> usize {
+            while a != b {
+                while rpo_idx[a] > rpo_idx[b] {
+                    a = idom[a].unwrap();
+                }
+                while rpo_idx[b] > rpo_idx[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &p in &cfg.blocks[b].preds {
+                    if idom[p].is_none() {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        None => Some(p),
+                        Some(d) => Some(intersect(&idom, p, d)),
+                    };
+                }
+                if new_idom != idom[b] {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); len];
+        for (b, d) in idom.iter().enumerate() {
+            if let Some(d) = d && *d != b {
+                children[*d].push(b);
+            }
+        }
+
+        // 入口は自分自身を支配ブロックとしていたので外す
+        idom[0] = None;
+        DomTree { idom, children }
+    }
+
+    /// 支配辺境 (dominance frontier) を求める
+    /// - 合流するブロックから各先行ブロックの支配木を遡る
+    pub fn frontiers(&self, cfg: &Cfg) -> Vec<Vec<usize>> {
+        let mut df = vec![Vec::new(); cfg.blocks.len()];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            if block.preds.len() < 2 {
+                continue;
+            }
+            for &p in &block.preds {
+                let mut runner = Some(p);
+                while let Some(r) = runner {
+                    if Some(r) == self.idom[b] || !self.is_reachable(r) {
+                        break;
+                    }
+                    if !df[r].contains(&b) {
+                        df[r].push(b);
+                    }
+                    runner = self.idom[r];
+                }
+            }
+        }
+        df
+    }
+
+    /// aがbを支配しているか調べる
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut runner = Some(b);
+        while let Some(r) = runner {
+            if r == a {
+                return true;
+            }
+            runner = self.idom[r];
+        }
+        false
+    }
+
+    fn is_reachable(&self, b: usize) -> bool {
+        b == 0 || self.idom[b].is_some()
+    }
+}
+
+#[test]
+/// ループを含むグラフの支配木と支配辺境
+fn test_dom_loop() {
+    use crate::ir::types_ir::{Label, ThreeAddressCode as TAC, VirtualReg};
+
+    // 0: 入口, 1: ループの先頭, 2: if, 3: then, 4: else, 5: 合流, 6: 出口
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::IfFalse { cond: VirtualReg { id: 0 }, label: Label::Lend(1) },
+        TAC::IfFalse { cond: VirtualReg { id: 0 }, label: Label::Lelse(2) },
+        TAC::GoTo { label: Label::Lend(3) },
+        TAC::Label { label: Label::Lelse(2) },
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 },
+        TAC::Label { label: Label::Lend(3) },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 0 } },
+    ];
+    let cfg = Cfg::new(&ir);
+    let dom = DomTree::new(&cfg);
+
+    assert_eq!(dom.idom, vec![None, Some(0), Some(1), Some(2), Some(2), Some(2), Some(1)]);
+    assert!(dom.dominates(1, 5));
+    assert!(!dom.dominates(3, 5));
+
+    let df = dom.frontiers(&cfg);
+    assert_eq!(df, vec![vec![], vec![1], vec![1], vec![5], vec![5], vec![1], vec![]]);
+}
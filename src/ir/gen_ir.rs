@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
-use crate::types::{ BinOp, Expr, Stmt };
-use crate::ir::types_ir::{ BinOp as IrBinOp, ThreeAddressCode as TAC, VirtualReg, Label, Param };
+use crate::types::{ BinOp, Expr, ExprKind, Stmt, StmtKind, Type };
+use crate::ir::types_ir::{ BinOp as IrBinOp, ThreeAddressCode as TAC, VirtualReg, Label, Param, Width };
 
 #[derive(Clone)]
 pub struct GenIrContext {
@@ -9,6 +9,21 @@ pub struct GenIrContext {
     register_count: usize,
     pub label_count: usize,
     lvar_map: HashMap<String, VirtualReg>,
+    /// 変数の型 (スタック上の領域の大きさに使う)
+    var_types: HashMap<VirtualReg, Type>,
+    /// グローバル変数の型
+    pub globals: HashMap<String, Type>,
+    /// 文字列リテラルの中身 (添字がラベルの番号になる)
+    /// - 関数をまたいで番号が重ならないように呼び出し側で引き継ぐ
+    pub strings: Vec<Vec<u8>>,
+    /// 文ごとに`Loc`を出力する (デバッグ情報用)
+    pub debug_info: bool,
+}
+
+impl Default for GenIrContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GenIrContext {
@@ -18,6 +33,10 @@ impl GenIrContext {
             register_count: 0,
             label_count: 0,
             lvar_map: HashMap::new(),
+            var_types: HashMap::new(),
+            globals: HashMap::new(),
+            strings: Vec::new(),
+            debug_info: false,
         }
     }
     
@@ -33,6 +52,12 @@ impl GenIrContext {
         self.lvar_map.clone()
     }
 
+    /// - 変数の仮想レジスタと型のHashMapを取得する
+    /// - `Frame`で使用
+    pub fn get_var_types(&self) -> HashMap<VirtualReg, Type> {
+        self.var_types.clone()
+    }
+
     /// 新しい仮想レジスタを作る
     fn get_new_register(&mut self) -> VirtualReg {
         let id = self.register_count;
@@ -52,6 +77,15 @@ impl GenIrContext {
         }
     }
     
+    /// 変数がグローバル変数ならその型を返す
+    /// - 同じ名前のローカル変数が宣言済みならそちらを優先する
+    fn global_type(&self, name: &str) -> Option<Type> {
+        if self.lvar_map.contains_key(name) {
+            return None;
+        }
+        self.globals.get(name).cloned()
+    }
+
     /// ラベルの番号を返す
     fn get_label_count(&mut self) -> usize {
         let i = self.label_count;
@@ -67,12 +101,32 @@ impl GenIrContext {
 pub fn stmt_to_ir(stmt: &Stmt, context: &mut GenIrContext) {
     // stmt_to_irは文を生成するとき
     // expr_to_irは式を生成して値の入ったレジスタを受け取るとき
-    match stmt {
-        Stmt::Return(expr) => {
+    // 文の位置を記録する (デバッグ情報用)
+    // 忘れてバグの原因になるためワイルドカードを使わない
+    let has_loc = match &stmt.kind {
+        StmtKind::Return(_) |
+        StmtKind::ExprStmt(_) |
+        StmtKind::If { .. } |
+        StmtKind::While { .. } |
+        StmtKind::For { .. } => true,
+        // ブロックと関数は中の文ごとに位置を出す
+        // 変数宣言は命令にならない
+        StmtKind::Block(_) |
+        StmtKind::Fn { .. } |
+        StmtKind::FnDecl { .. } |
+        StmtKind::GlobalVar { .. } |
+        StmtKind::VarDecl { .. } => false,
+    };
+    if context.debug_info && has_loc {
+        context.emit(TAC::Loc { span: stmt.span });
+    }
+
+    match &stmt.kind {
+        StmtKind::Return(expr) => {
             let src = expr_to_ir(expr, context);
             context.emit(TAC::Return { src });
         }
-        Stmt::While { cond: _cond, body: _body } => {
+        StmtKind::While { cond: _cond, body: _body } => {
             // begin:
             //   if (a == 0)
             //     goto end;
@@ -91,7 +145,7 @@ pub fn stmt_to_ir(stmt: &Stmt, context: &mut GenIrContext) {
             // Lendラベル
             context.emit(TAC::Label { label: end });
         }
-        Stmt::For { init: _init, cond: _cond, update: _update, body: _body } => {
+        StmtKind::For { init: _init, cond: _cond, update: _update, body: _body } => {
             // // for (init; cond; update) body;
             // init;
             // begin:
@@ -126,7 +180,7 @@ pub fn stmt_to_ir(stmt: &Stmt, context: &mut GenIrContext) {
             context.emit(TAC::GoTo { label: begin });
             context.emit(TAC::Label { label: end });
         }
-        Stmt::If { cond: _cond, then, els: _els } => {
+        StmtKind::If { cond: _cond, then, els: _els } => {
             //   if (cond == 0)
             //     goto Lelse;
             //   then;
@@ -163,100 +217,232 @@ pub fn stmt_to_ir(stmt: &Stmt, context: &mut GenIrContext) {
                 context.emit(TAC::Label { label: label_end });
             }
         }
-        Stmt::Block(stmts) => {
+        StmtKind::Block(stmts) => {
             for stmt in stmts {
                 stmt_to_ir(stmt, context);
             }
         }
-        Stmt::Fn { fn_name, params: _params, body } => {
+        StmtKind::Fn { fn_name, params: _params, body } => {
             let mut params = Vec::new();
             for param in _params {
-                let name = match param {
-                    Expr::Var(name) => name.to_owned(),
+                let name = match &param.kind {
+                    ExprKind::Var(name) => name.to_owned(),
                     _ => unreachable!("parameter should be identifier but got {:?}", param)
                 };
                 let dest = context.get_var_reg(&name);
-                params.push(Param::new(dest, name));
+                // 引数の型はパーサで付けてある
+                let ty = param.ty.clone().unwrap();
+                params.push(Param::new(dest, name, Width::from_size(ty.size())));
+                context.var_types.insert(dest, ty);
             }
 
             context.emit(TAC::Fn { fn_name: fn_name.clone(), params });
+            // プロローグは関数定義の位置にする
+            if context.debug_info {
+                context.emit(TAC::Loc { span: stmt.span });
+            }
             
             for stmt in body {
                 stmt_to_ir(stmt, context);
             }
         }
-        Stmt::ExprStmt(expr) => {
+        StmtKind::ExprStmt(expr) => {
             expr_to_ir(expr, context);
         }
-        Stmt::VarDecl { name, ..  } => {
-            context.get_var_reg(name);
+        StmtKind::VarDecl { name, ty } => {
+            let reg = context.get_var_reg(name);
+            context.var_types.insert(reg, ty.clone());
+        }
+        StmtKind::FnDecl { .. } => {
+            // プロトタイプ宣言は命令にならない
+        }
+        StmtKind::GlobalVar { .. } => {
+            // グローバル変数は`gen_x86_64::gen_globals`で出力する
         }
     }
 
 }
 
-fn gen_lval_addr(expr: &Expr, context: &mut GenIrContext) -> VirtualReg {
-    match expr {
-        Expr::Deref(_var) => {
-            let addr = gen_lval_addr(&_var, context);
-            match &**_var {
-                // 参照外しが続いているとき
-                // **pp はまず *pp (LoadVar) をする
-                Expr::Deref(_) => {
-                    let value = context.get_new_register();
-                    context.emit(TAC::LoadVar { value, addr });
-                    value
-                }
-                _ => {
-                    addr
-                }
-            }
-            
+/// 式がポインタならその指す先の大きさを返す
+/// - 型は`sema::check`で付けたものを使う
+/// - 配列は先頭要素へのポインタとして扱う
+fn pointee_size(expr: &Expr) -> Option<usize> {
+    match &expr.ty {
+        Some(Type::Ptr(base) | Type::Array(base, _)) => Some(base.size()),
+        Some(Type::Int | Type::Char | Type::Struct(_)) | None => None,
+    }
+}
+
+/// 値の代わりに先頭のアドレスで扱う型か調べる
+/// - 配列と構造体はレジスタに載らないため
+fn is_addr_value(ty: Option<&Type>) -> bool {
+    matches!(ty, Some(Type::Array(..) | Type::Struct(_)))
+}
+
+/// 式の値を読み書きするときの幅
+/// - 型が付いていないときはintとして扱う
+fn width_of(expr: &Expr) -> Width {
+    match &expr.ty {
+        Some(ty) => Width::from_size(ty.size()),
+        None => Width::Dword,
+    }
+}
+
+/// 整数を`size`倍する (ポインタの足し算・引き算用)
+/// - アドレスの計算なので64bitで行う
+fn scale(reg: VirtualReg, size: usize, context: &mut GenIrContext) -> VirtualReg {
+    let size_reg = context.get_new_register();
+    context.emit(TAC::LoadImm { dest: size_reg, value: size as i32 });
+    let dest = context.get_new_register();
+    context.emit(TAC::BinOpCode { dest, left: reg, op: IrBinOp::Mul, right: size_reg, width: Width::Qword });
+    dest
+}
+
+/// 変数をメモリを介して読み書きするときの幅
+/// - charの変数はレジスタに昇格すると切り捨てが行われないため,
+///   アドレスを取って常にスタック上で読み書きする
+fn var_mem_width(var: VirtualReg, context: &GenIrContext) -> Option<Width> {
+    match context.var_types.get(&var) {
+        Some(Type::Char) => Some(Width::Byte),
+        Some(Type::Int | Type::Ptr(_) | Type::Array(..) | Type::Struct(_)) | None => None,
+    }
+}
+
+/// アドレスに定数を足す
+fn add_offset(addr: VirtualReg, offset: usize, context: &mut GenIrContext) -> VirtualReg {
+    if offset == 0 {
+        return addr;
+    }
+    let offset_reg = context.get_new_register();
+    context.emit(TAC::LoadImm { dest: offset_reg, value: offset as i32 });
+    let dest = context.get_new_register();
+    context.emit(TAC::BinOpCode { dest, left: addr, op: IrBinOp::Add, right: offset_reg, width: Width::Qword });
+    dest
+}
+
+/// 構造体を`src`から`dest`にコピーする
+/// - 8バイト, 4バイト, 1バイトの順に大きい単位で読み書きする
+fn copy_struct(dest: VirtualReg, src: VirtualReg, size: usize, context: &mut GenIrContext) {
+    let mut offset = 0;
+    for (chunk, width) in [(8, Width::Qword), (4, Width::Dword), (1, Width::Byte)] {
+        while size - offset >= chunk {
+            let from = add_offset(src, offset, context);
+            let value = context.get_new_register();
+            context.emit(TAC::LoadVar { value, addr: from, width });
+            let to = add_offset(dest, offset, context);
+            context.emit(TAC::Store { addr: to, src: value, width });
+            offset += chunk;
         }
-        Expr::Var(name) => {
-            let dest = context.get_var_reg(&name);
-            context.emit(TAC::EvalVar { 
-                dest, 
-                name: name.to_string()
-            });
+    }
+}
+
+/// 代入先のアドレスを計算する
+/// - `*e`のアドレスは`e`の値
+/// - `e.x`のアドレスは`e`のアドレスにメンバのオフセットを足したもの
+fn gen_lval_addr(expr: &Expr, context: &mut GenIrContext) -> VirtualReg {
+    match &expr.kind {
+        ExprKind::Var(name) if context.global_type(name).is_some() => {
+            let dest = context.get_new_register();
+            context.emit(TAC::GlobalAddr { dest, name: name.clone() });
             dest
         }
-        _ => unreachable!(),
+        ExprKind::Var(name) => {
+            let var = context.get_var_reg(name);
+            let addr = context.get_new_register();
+            context.emit(TAC::AddrOf { addr, var });
+            addr
+        }
+        ExprKind::Deref(addr) => expr_to_ir(addr, context),
+        ExprKind::Member { base, name } => {
+            // 構造体の式の値は先頭のアドレス
+            let addr = expr_to_ir(base, context);
+            let offset = match &base.ty {
+                Some(Type::Struct(agg)) => agg.member(name).expect("member should be checked by sema").offset,
+                _ => unreachable!("member access to non-struct value: {:?}", base),
+            };
+            add_offset(addr, offset, context)
+        }
+        _ => unreachable!("left value got not assingnable node: {:?}", expr),
     }
 }
 
 fn expr_to_ir(expr: &Expr, context: &mut GenIrContext) -> VirtualReg {
-    match expr {
-        Expr::Assign { lhs, rhs } => {
+    match &expr.kind {
+        ExprKind::Assign { lhs, rhs } => {
             let src = expr_to_ir(rhs, context);
             
-            match &**lhs {
-                Expr::Deref(_) => {
-                    let addr = gen_lval_addr(&lhs, context);
-                    context.emit(TAC::Store { addr, src });
+            // 構造体は中身をコピーする
+            if let Some(Type::Struct(agg)) = &lhs.ty {
+                let dest = gen_lval_addr(lhs, context);
+                copy_struct(dest, src, agg.size(), context);
+                return dest;
+            }
+
+            match &lhs.kind {
+                ExprKind::Deref(_) | ExprKind::Member { .. } => {
+                    let addr = gen_lval_addr(lhs, context);
+                    context.emit(TAC::Store { addr, src, width: width_of(lhs) });
                 }
-                Expr::Var(name) => {
+                ExprKind::Var(name) if context.global_type(name).is_some() => {
+                    let width = width_of(lhs);
+                    context.emit(TAC::StoreGlobal { name: name.clone(), src, width });
+                }
+                ExprKind::Var(name) => {
                     let dest = context.get_var_reg(name);
-                    context.emit(TAC::Assign { 
-                        dest, 
-                        src,
-                    });
+                    if let Some(width) = var_mem_width(dest, context) {
+                        let addr = context.get_new_register();
+                        context.emit(TAC::AddrOf { addr, var: dest });
+                        context.emit(TAC::Store { addr, src, width });
+                    } else {
+                        context.emit(TAC::Assign { 
+                            dest, 
+                            src,
+                        });
+                    }
                 }
                 _ => unreachable!("left value got not assingnable node: {:?}", lhs),
             }
-            return src;
+            src
         }
-        Expr::Num(val) => {
+        ExprKind::Num(val) => {
             let reg = context.get_new_register();
-            context.emit(TAC::LoadImm { dest: reg.clone(), value: *val });
+            context.emit(TAC::LoadImm { dest: reg, value: *val });
             reg
         }
-        Expr::Binary { op: _op, lhs, rhs } => {
+        ExprKind::Str(bytes) => {
+            let dest = context.get_new_register();
+            let id = context.strings.len();
+            context.strings.push(bytes.clone());
+            context.emit(TAC::StrAddr { dest, id });
+            dest
+        }
+        ExprKind::Binary { op: _op, lhs, rhs } => {
             // ここは即値入れるなどの最適化しない
             // divとそれ以外で場合分けが発生して面倒なことになる
             // 単一責務
-            let left_operand = expr_to_ir(&lhs, context);
-            let right_operand = expr_to_ir(&rhs, context);
+            let mut left_operand = expr_to_ir(lhs, context);
+            let mut right_operand = expr_to_ir(rhs, context);
+
+            // ポインタと整数の足し算・引き算は整数を指す先の大きさ倍する
+            // ポインタ同士の引き算は最後に指す先の大きさで割る
+            let mut diff_size = None;
+            // ポインタが関わる演算は64bitで行う
+            let width = match (pointee_size(lhs), pointee_size(rhs)) {
+                (None, None) => Width::Dword,
+                _ => Width::Qword,
+            };
+            match (_op, pointee_size(lhs), pointee_size(rhs)) {
+                (BinOp::Add | BinOp::Sub, Some(size), None) => {
+                    right_operand = scale(right_operand, size, context);
+                }
+                (BinOp::Add, None, Some(size)) => {
+                    left_operand = scale(left_operand, size, context);
+                }
+                (BinOp::Sub, Some(size), Some(_)) => {
+                    diff_size = Some(size);
+                }
+                _ => (),
+            }
 
             let dest_vreg = context.get_new_register();
             let op = match _op {
@@ -274,43 +460,81 @@ fn expr_to_ir(expr: &Expr, context: &mut GenIrContext) -> VirtualReg {
                 left: left_operand,
                 op,
                 right: right_operand,
+                width,
             });
-            dest_vreg
+
+            match diff_size {
+                Some(size) => {
+                    let size_reg = context.get_new_register();
+                    context.emit(TAC::LoadImm { dest: size_reg, value: size as i32 });
+                    let quot = context.get_new_register();
+                    context.emit(TAC::BinOpCode { dest: quot, left: dest_vreg, op: IrBinOp::Div, right: size_reg, width: Width::Qword });
+                    quot
+                }
+                None => dest_vreg,
+            }
         }
-        Expr::Var(name) => {
-            let dest = context.get_var_reg(&name);
+        ExprKind::Var(name) => {
+            if let Some(ty) = context.global_type(name) {
+                let dest = context.get_new_register();
+                if is_addr_value(Some(&ty)) {
+                    context.emit(TAC::GlobalAddr { dest, name: name.clone() });
+                } else {
+                    context.emit(TAC::LoadGlobal { dest, name: name.clone(), width: Width::from_size(ty.size()) });
+                }
+                return dest;
+            }
+            let dest = context.get_var_reg(name);
+            // 配列と構造体は先頭のアドレスになる
+            if is_addr_value(context.var_types.get(&dest)) {
+                let addr = context.get_new_register();
+                context.emit(TAC::AddrOf { addr, var: dest });
+                return addr;
+            }
+            if let Some(width) = var_mem_width(dest, context) {
+                let addr = context.get_new_register();
+                context.emit(TAC::AddrOf { addr, var: dest });
+                let value = context.get_new_register();
+                context.emit(TAC::LoadVar { value, addr, width });
+                return value;
+            }
             context.emit(TAC::EvalVar { 
                 dest, 
                 name: name.clone()
             });
             dest
         }
-        Expr::Addr(_name) => {
-            // TODO: 型検証を導入するまではpanicする
-            // nameフィールドを埋めているのが変数名であること
-            let name = match &**_name {
-                Expr::Var(n) => n,
-                _ => unreachable!("Addr has value that is not able to referenced (it should be a bug in parser!)")
-            };
-            let var = context.get_var_reg(&name);
-            let addr = context.get_new_register();
-            context.emit(TAC::AddrOf { addr, var });
-            addr
+        ExprKind::Addr(lval) => {
+            // アドレスを取れるのは変数, `*e`, `e.x`のみ (パーサで確認済み)
+            gen_lval_addr(lval, context)
         }
-        Expr::Deref(deref) => {
+        ExprKind::Deref(deref) => {
+            let addr = expr_to_ir(deref, context);
+            // 指す先が配列や構造体のときは読み込まずにアドレスのまま使う
+            if is_addr_value(expr.ty.as_ref()) {
+                return addr;
+            }
             let dest = context.get_new_register();
-            let addr = expr_to_ir(&deref, context);
-            context.emit(TAC::LoadVar { value: dest, addr });
+            context.emit(TAC::LoadVar { value: dest, addr, width: width_of(expr) });
             dest
         }
-        Expr::Call { fn_name, args: _args } => {
+        ExprKind::Call { fn_name, args: _args } => {
             let mut args = Vec::new();
             for arg in _args {
-                args.push(expr_to_ir(&arg, context));
+                args.push(expr_to_ir(arg, context));
             }
             let ret_reg = context.get_new_register();
-            context.emit(TAC::Call { fn_name: fn_name.clone(), args, ret_reg });
+            context.emit(TAC::Call { fn_name: fn_name.clone(), args, ret_reg, width: width_of(expr) });
             ret_reg
         }
+        ExprKind::Member { .. } => {
+            let addr = gen_lval_addr(expr, context);
+            if is_addr_value(expr.ty.as_ref()) {
+                return addr;
+            }
+            let value = context.get_new_register();
+            context.emit(TAC::LoadVar { value, addr, width: width_of(expr) });
+            value
+        }
     }
 }
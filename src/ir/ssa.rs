@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::cfg::Cfg;
+use crate::ir::dominance::DomTree;
+use crate::ir::types_ir::{ThreeAddressCode as TAC, VirtualReg};
+use crate::reg_alloc::liveness;
+
+/// SSA形式に変換する
+/// - アドレスを取られていない変数を仮想レジスタに昇格する
+/// - 昇格した変数は`lvar_map`から取り除く (スタックに置かなくて良い)
+/// - φ関数は支配辺境のうち変数が生存しているブロックにだけ置く
+/// - 変数の読み出し (`EvalVar`) は取り除き, 使用箇所を最新の版に置き換える
+pub fn construct(cfg: &mut Cfg, lvar_map: &mut HashMap<String, VirtualReg>) {
+    // 到達できないブロックは名前の付け替えで辿られないので先に消す
+    cfg.remove_unreachable();
+
+    // アドレスを取られていない変数を昇格する
+    let mut vars: HashSet<VirtualReg> = lvar_map.values().copied().collect();
+    for tac in cfg.blocks.iter().flat_map(|b| b.code.iter()) {
+        if let TAC::AddrOf { var, .. } = tac {
+            vars.remove(var);
+        }
+    }
+    if vars.is_empty() {
+        return;
+    }
+    lvar_map.retain(|_, v| !vars.contains(v));
+
+    // 各ブロックの入口で生存している変数を調べる
+    let code = cfg.to_code();
+    let liveness = liveness::analyze(&code);
+    let mut block_live_in = Vec::new();
+    let mut idx = 0;
+    for block in &cfg.blocks {
+        block_live_in.push(liveness.live_in.get(idx).cloned().unwrap_or_default());
+        idx += block.code.len();
+    }
+
+    // 初期化されずに使われる変数は0で初期化しておく
+    // 入口で生存しているなら未定義のまま読まれる経路がある
+    let mut undef: Vec<VirtualReg> = block_live_in[0].iter()
+        .filter(|r| vars.contains(r))
+        .copied()
+        .collect();
+    undef.sort_by_key(|r| r.id);
+    let entry = &mut cfg.blocks[0].code;
+    let pos = if matches!(entry.first(), Some(TAC::Fn { .. })) { 1 } else { 0 };
+    for (i, var) in undef.into_iter().enumerate() {
+        entry.insert(pos + i, TAC::LoadImm { dest: var, value: 0 });
+    }
+
+    // 変数を定義しているブロックを集める
+    let mut def_blocks: HashMap<VirtualReg, Vec<usize>> = HashMap::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for tac in &block.code {
+            for reg in tac.get_def_regs() {
+                if vars.contains(&reg) {
+                    let blocks = def_blocks.entry(reg).or_default();
+                    if !blocks.contains(&b) {
+                        blocks.push(b);
+                    }
+                }
+            }
+        }
+    }
+
+    // φ関数を置く
+    let dom = DomTree::new(cfg);
+    let df = dom.frontiers(cfg);
+    let mut phi_vars: Vec<Vec<VirtualReg>> = vec![Vec::new(); cfg.blocks.len()];
+    let mut sorted_vars: Vec<VirtualReg> = vars.iter().copied().collect();
+    sorted_vars.sort_by_key(|r| r.id);
+    for var in &sorted_vars {
+        let mut work = def_blocks.get(var).cloned().unwrap_or_default();
+        let mut has_phi = HashSet::new();
+        while let Some(b) = work.pop() {
+            for &y in &df[b] {
+                if has_phi.contains(&y) || !block_live_in[y].contains(var) {
+                    continue;
+                }
+                has_phi.insert(y);
+                phi_vars[y].push(*var);
+                work.push(y);
+            }
+        }
+    }
+    for (b, block) in cfg.blocks.iter_mut().enumerate() {
+        let pos = if block.label().is_some() { 1 } else { 0 };
+        for (i, var) in phi_vars[b].iter().enumerate() {
+            block.code.insert(pos + i, TAC::Phi { dest: *var, args: Vec::new() });
+        }
+    }
+
+    // 支配木を辿って名前を付け替える
+    let mut renamer = Renamer {
+        vars: &vars,
+        phi_vars: &phi_vars,
+        stacks: HashMap::new(),
+        next_id: cfg.next_vreg_id(),
+    };
+    renamer.rename(cfg, &dom, 0);
+}
+
+/// 名前の付け替えの状態
+struct Renamer<'a> {
+    vars: &'a HashSet<VirtualReg>,
+    phi_vars: &'a Vec<Vec<VirtualReg>>,
+    /// 変数ごとの現在の版
+    stacks: HashMap<VirtualReg, Vec<VirtualReg>>,
+    next_id: usize,
+}
+
+impl Renamer<'_> {
+    /// 変数の新しい版を作る
+    fn new_version(&mut self, var: VirtualReg) -> VirtualReg {
+        let reg = VirtualReg { id: self.next_id };
+        self.next_id += 1;
+        self.stacks.entry(var).or_default().push(reg);
+        reg
+    }
+
+    /// 変数の現在の版を返す
+    fn current(&self, reg: VirtualReg) -> VirtualReg {
+        if !self.vars.contains(&reg) {
+            return reg;
+        }
+        let msg = format!("variable '{:?}' is used before definition", reg);
+        *self.stacks.get(&reg).and_then(|s| s.last()).expect(&msg)
+    }
+
+    fn rename(&mut self, cfg: &mut Cfg, dom: &DomTree, b: usize) {
+        let mut defined = Vec::new();
+
+        let code = std::mem::take(&mut cfg.blocks[b].code);
+        let mut renamed = Vec::new();
+        for mut tac in code {
+            match &mut tac {
+                TAC::Phi { dest, .. } => {
+                    // φ関数の引数は先行ブロックで埋める
+                    let var = *dest;
+                    *dest = self.new_version(var);
+                    defined.push(var);
+                }
+                TAC::EvalVar { dest, .. } if self.vars.contains(dest) => {
+                    // 読み出しは使用箇所の置き換えで済む
+                    continue;
+                }
+                _ => {
+                    tac.map_use_regs(|r| self.current(r));
+                    let mut defs = Vec::new();
+                    tac.map_def_regs(|r| {
+                        if self.vars.contains(&r) {
+                            defs.push(r);
+                        }
+                        r
+                    });
+                    let mut versions = HashMap::new();
+                    for var in defs {
+                        versions.insert(var, self.new_version(var));
+                        defined.push(var);
+                    }
+                    tac.map_def_regs(|r| *versions.get(&r).unwrap_or(&r));
+                }
+            }
+            renamed.push(tac);
+        }
+        cfg.blocks[b].code = renamed;
+
+        // 後続ブロックのφ関数に現在の版を渡す
+        for s in cfg.blocks[b].succs.clone() {
+            let pos = if cfg.blocks[s].label().is_some() { 1 } else { 0 };
+            for (i, var) in self.phi_vars[s].iter().enumerate() {
+                let src = self.current(*var);
+                match &mut cfg.blocks[s].code[pos + i] {
+                    TAC::Phi { args, .. } => args.push((b, src)),
+                    other => unreachable!("expected phi but got {:?}", other),
+                }
+            }
+        }
+
+        for child in dom.children[b].clone() {
+            self.rename(cfg, dom, child);
+        }
+
+        for var in defined {
+            self.stacks.get_mut(&var).unwrap().pop();
+        }
+    }
+}
+
+/// SSA形式から戻す
+/// - φ関数を先行ブロックの末尾でのコピー (`Assign`) に置き換える
+/// - 同じ場所のコピーは同時に行われるものとして, 必要なら一時レジスタを介す
+pub fn destruct(cfg: &mut Cfg) {
+    let mut next_id = cfg.next_vreg_id();
+
+    // 先行ブロックごとにコピーを集める
+    let mut copies: Vec<Vec<(VirtualReg, VirtualReg)>> = vec![Vec::new(); cfg.blocks.len()];
+    for block in &mut cfg.blocks {
+        block.code.retain(|tac| {
+            if let TAC::Phi { dest, args } = tac {
+                for (pred, src) in args {
+                    copies[*pred].push((*dest, *src));
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    for (b, block_copies) in copies.into_iter().enumerate() {
+        if block_copies.is_empty() {
+            continue;
+        }
+
+        // コピー先が別のコピー元になっている場合は先に退避する
+        let dests: HashSet<VirtualReg> = block_copies.iter().map(|(d, _)| *d).collect();
+        let mut seq = Vec::new();
+        let mut pending = Vec::new();
+        for (dest, src) in block_copies {
+            if dest == src {
+                continue;
+            }
+            if dests.contains(&src) {
+                let tmp = VirtualReg { id: next_id };
+                next_id += 1;
+                seq.push(TAC::Assign { dest: tmp, src });
+                pending.push(TAC::Assign { dest, src: tmp });
+            } else {
+                pending.push(TAC::Assign { dest, src });
+            }
+        }
+        seq.extend(pending);
+
+        // 分岐の直前に置く
+        let code = &mut cfg.blocks[b].code;
+        let pos = match code.last() {
+            Some(TAC::GoTo { .. }) | Some(TAC::IfFalse { .. }) | Some(TAC::Return { .. }) => code.len() - 1,
+            _ => code.len(),
+        };
+        code.splice(pos..pos, seq);
+    }
+}
+
+#[cfg(test)]
+/// (ブロックの番号, φ関数) を列挙する
+fn phis(cfg: &Cfg) -> Vec<(usize, TAC)> {
+    let mut result = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for tac in &block.code {
+            if let TAC::Phi { .. } = tac {
+                result.push((b, tac.clone()));
+            }
+        }
+    }
+    result
+}
+
+#[test]
+/// if-elseの合流点にφ関数が置かれる
+fn test_ssa_if_else() {
+    use crate::ir::types_ir::Label;
+
+    // int x; if (c) x = 1; else x = 2; return x;
+    let x = VirtualReg { id: 0 };
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        TAC::IfFalse { cond: VirtualReg { id: 1 }, label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 1 },
+        TAC::Assign { dest: x, src: VirtualReg { id: 2 } },
+        TAC::GoTo { label: Label::Lend(1) },
+        TAC::Label { label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 2 },
+        TAC::Assign { dest: x, src: VirtualReg { id: 3 } },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::EvalVar { dest: x, name: "x".to_string() },
+        TAC::Return { src: x },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    let mut lvar_map = HashMap::from([("x".to_string(), x)]);
+    construct(&mut cfg, &mut lvar_map);
+
+    assert!(lvar_map.is_empty());
+    let expected = vec![
+        (3, TAC::Phi { dest: VirtualReg { id: 6 }, args: vec![(1, VirtualReg { id: 4 }), (2, VirtualReg { id: 5 })] }),
+    ];
+    assert_eq!(phis(&cfg), expected);
+    assert_eq!(cfg.blocks[3].code, vec![
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Phi { dest: VirtualReg { id: 6 }, args: vec![(1, VirtualReg { id: 4 }), (2, VirtualReg { id: 5 })] },
+        TAC::Return { src: VirtualReg { id: 6 } },
+    ]);
+
+    // φ関数は先行ブロックのコピーになる
+    destruct(&mut cfg);
+    assert!(phis(&cfg).is_empty());
+    assert_eq!(cfg.blocks[1].code, vec![
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 1 },
+        TAC::Assign { dest: VirtualReg { id: 4 }, src: VirtualReg { id: 2 } },
+        TAC::Assign { dest: VirtualReg { id: 6 }, src: VirtualReg { id: 4 } },
+        TAC::GoTo { label: Label::Lend(1) },
+    ]);
+}
+
+#[test]
+/// ループの先頭にφ関数が置かれ, 後方分岐から値を受け取る
+/// - アドレスを取られた変数は昇格しない
+fn test_ssa_loop() {
+    use crate::ir::types_ir::{BinOp, Label, Width};
+
+    // int i; int y; i = 0; &y; while (i < 10) i = i + 1; return i;
+    let i = VirtualReg { id: 0 };
+    let y = VirtualReg { id: 1 };
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 0 },
+        TAC::Assign { dest: i, src: VirtualReg { id: 2 } },
+        TAC::AddrOf { addr: VirtualReg { id: 3 }, var: y },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::EvalVar { dest: i, name: "i".to_string() },
+        TAC::LoadImm { dest: VirtualReg { id: 4 }, value: 10 },
+        TAC::BinOpCode { dest: VirtualReg { id: 5 }, left: i, op: BinOp::Lt, right: VirtualReg { id: 4 }, width: Width::Dword },
+        TAC::IfFalse { cond: VirtualReg { id: 5 }, label: Label::Lend(1) },
+        TAC::EvalVar { dest: i, name: "i".to_string() },
+        TAC::LoadImm { dest: VirtualReg { id: 6 }, value: 1 },
+        TAC::BinOpCode { dest: VirtualReg { id: 7 }, left: i, op: BinOp::Add, right: VirtualReg { id: 6 }, width: Width::Dword },
+        TAC::Assign { dest: i, src: VirtualReg { id: 7 } },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::EvalVar { dest: i, name: "i".to_string() },
+        TAC::Return { src: i },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    let mut lvar_map = HashMap::from([("i".to_string(), i), ("y".to_string(), y)]);
+    construct(&mut cfg, &mut lvar_map);
+
+    assert_eq!(lvar_map, HashMap::from([("y".to_string(), y)]));
+    // i0 = v8, ループの先頭の i = v9, ループ内の i = v10
+    let expected = vec![
+        (1, TAC::Phi { dest: VirtualReg { id: 9 }, args: vec![(0, VirtualReg { id: 8 }), (2, VirtualReg { id: 10 })] }),
+    ];
+    assert_eq!(phis(&cfg), expected);
+    assert_eq!(cfg.blocks[3].code.last(), Some(&TAC::Return { src: VirtualReg { id: 9 } }));
+    let has_eval_var = cfg.blocks.iter()
+        .flat_map(|b| b.code.iter())
+        .any(|tac| matches!(tac, TAC::EvalVar { .. }));
+    assert!(!has_eval_var);
+}
+
+#[test]
+/// 同時に行うコピーが互いに依存する場合は一時レジスタを介す
+fn test_destruct_swap() {
+    use crate::ir::types_ir::Label;
+
+    let a = VirtualReg { id: 0 };
+    let b = VirtualReg { id: 1 };
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 1 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::Phi { dest: a, args: vec![(0, VirtualReg { id: 2 }), (1, b)] },
+        TAC::Phi { dest: b, args: vec![(0, VirtualReg { id: 2 }), (1, a)] },
+        TAC::GoTo { label: Label::Lbegin(0) },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    destruct(&mut cfg);
+
+    assert_eq!(cfg.blocks[1].code, vec![
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::Assign { dest: VirtualReg { id: 3 }, src: b },
+        TAC::Assign { dest: VirtualReg { id: 4 }, src: a },
+        TAC::Assign { dest: a, src: VirtualReg { id: 3 } },
+        TAC::Assign { dest: b, src: VirtualReg { id: 4 } },
+        TAC::GoTo { label: Label::Lbegin(0) },
+    ]);
+}
@@ -1,3 +1,5 @@
+use crate::types::Span;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct VirtualReg{
     pub id: usize,
@@ -9,7 +11,28 @@ pub enum BinOp {
     Le, Lt, Eq, Ne,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// メモリの読み書きと演算の幅
+/// - レジスタ上の値は常に64bitに符号拡張しておく
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Width {
+    Byte,  // 8bit (char)
+    Dword, // 32bit (int)
+    Qword, // 64bit (ポインタ)
+}
+
+impl Width {
+    /// 型の大きさ (バイト) から幅を決める
+    pub fn from_size(size: usize) -> Self {
+        match size {
+            1 => Width::Byte,
+            4 => Width::Dword,
+            8 => Width::Qword,
+            _ => unreachable!("no width for {} bytes", size),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Label {
     Lelse(usize),
     Lbegin(usize),
@@ -20,56 +43,145 @@ pub enum Label {
 pub struct Param {
     pub dest: VirtualReg,
     pub name: String,
+    /// 受け取る値の幅 (引数の型の大きさ)
+    pub width: Width,
 }
 
 impl Param {
-    pub fn new(dest: VirtualReg, name: String) -> Self {
-        Param { dest, name }
+    pub fn new(dest: VirtualReg, name: String, width: Width) -> Self {
+        Param { dest, name, width }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ThreeAddressCode {
     LoadImm { dest: VirtualReg, value: i32 },
-    BinOpCode { dest: VirtualReg, left: VirtualReg, op: BinOp, right: VirtualReg },
+    BinOpCode { dest: VirtualReg, left: VirtualReg, op: BinOp, right: VirtualReg, width: Width },
     Assign { dest: VirtualReg, src: VirtualReg },
     EvalVar { dest: VirtualReg, name: String }, // 生存期間の扱いを分かりやすく扱うために必要
     AddrOf { addr: VirtualReg, var: VirtualReg }, // 変数のアドレスを取る (&a)
-    LoadVar { value: VirtualReg, addr: VirtualReg }, // 参照外し (*p)
-    Store { addr: VirtualReg, src: VirtualReg }, // 間接ストア (*p = v)
+    LoadVar { value: VirtualReg, addr: VirtualReg, width: Width }, // 参照外し (*p)
+    Store { addr: VirtualReg, src: VirtualReg, width: Width }, // 間接ストア (*p = v)
+    GlobalAddr { dest: VirtualReg, name: String }, // グローバル変数のアドレス (&g)
+    LoadGlobal { dest: VirtualReg, name: String, width: Width }, // グローバル変数の読み込み
+    StoreGlobal { name: String, src: VirtualReg, width: Width }, // グローバル変数への書き込み
+    StrAddr { dest: VirtualReg, id: usize }, // id番目の文字列リテラルのアドレス
     Return { src: VirtualReg },
     IfFalse { cond: VirtualReg, label: Label }, // condが0ならlabelに飛ぶ
     GoTo { label: Label },
     Label { label: Label },
-    Call { fn_name: String, args: Vec<VirtualReg>, ret_reg: VirtualReg },
+    Call { fn_name: String, args: Vec<VirtualReg>, ret_reg: VirtualReg, width: Width }, // widthは戻り値の幅
     Fn { fn_name: String, params: Vec<Param> },
+    Phi { dest: VirtualReg, args: Vec<(usize, VirtualReg)> }, // SSA形式の合流 (先行ブロックの番号, 値)
+    Loc { span: Span }, // 以降の命令に対応するソースコード上の位置 (デバッグ情報用)
 }
 
 impl ThreeAddressCode {
-    /// 命令が使用しているレジスタを列挙して配列を返す
-    pub(crate) fn get_using_regs(&self) -> Vec<VirtualReg> {
+    /// 命令が値を書き込むレジスタを列挙して配列を返す
+    pub(crate) fn get_def_regs(&self) -> Vec<VirtualReg> {
         match self {
             ThreeAddressCode::LoadImm { dest, .. } => {
                 vec![*dest]
             }
-            ThreeAddressCode::BinOpCode { dest, left, right ,.. } => {
-                vec![*dest, *left, *right]
+            ThreeAddressCode::BinOpCode { dest, .. } => {
+                vec![*dest]
+            }
+            ThreeAddressCode::Assign { dest, .. } => {
+                vec![*dest]
+            }
+            ThreeAddressCode::EvalVar { .. } => {
+                // 変数の値を読むだけで書き込みはしない
+                Vec::new()
+            }
+            ThreeAddressCode::AddrOf { addr, .. } => {
+                vec![*addr]
+            }
+            ThreeAddressCode::LoadVar { value, .. } => {
+                vec![*value]
+            }
+            ThreeAddressCode::Store { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::GlobalAddr { dest, .. } => {
+                vec![*dest]
+            }
+            ThreeAddressCode::LoadGlobal { dest, .. } => {
+                vec![*dest]
             }
-            ThreeAddressCode::Assign { dest, src } => {
-                vec![*dest, *src]
+            ThreeAddressCode::StoreGlobal { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::StrAddr { dest, .. } => {
+                vec![*dest]
+            }
+            ThreeAddressCode::Return { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::IfFalse { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::GoTo { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::Label { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::Call { ret_reg, .. } => {
+                vec![*ret_reg]
+            }
+            ThreeAddressCode::Fn { params, .. } => {
+                let mut vregs = Vec::new();
+                for param in params {
+                    vregs.push(param.dest);
+                }
+                vregs
+            }
+            ThreeAddressCode::Phi { dest, .. } => {
+                vec![*dest]
+            }
+            ThreeAddressCode::Loc { .. } => {
+                Vec::new()
+            }
+            // 忘れてバグの原因になるためワイルドカードを使わない
+        }
+    }
+
+    /// 命令が値を読むレジスタを列挙して配列を返す
+    pub(crate) fn get_use_regs(&self) -> Vec<VirtualReg> {
+        match self {
+            ThreeAddressCode::LoadImm { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::BinOpCode { left, right ,.. } => {
+                vec![*left, *right]
+            }
+            ThreeAddressCode::Assign { src, .. } => {
+                vec![*src]
             }
             ThreeAddressCode::EvalVar { dest, .. } => {
                 vec![*dest]
             }
-            ThreeAddressCode::AddrOf { addr, var } => {
-                vec![*addr, *var]
+            ThreeAddressCode::AddrOf { var, .. } => {
+                vec![*var]
             }
-            ThreeAddressCode::LoadVar { value: dest, addr: src } => {
-                vec![*dest, *src]
+            ThreeAddressCode::LoadVar { addr, .. } => {
+                vec![*addr]
             }
-            ThreeAddressCode::Store { addr, src } => {
+            ThreeAddressCode::Store { addr, src, .. } => {
                 vec![*addr, *src]
             }
+            ThreeAddressCode::GlobalAddr { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::LoadGlobal { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::StoreGlobal { src, .. } => {
+                vec![*src]
+            }
+            ThreeAddressCode::StrAddr { .. } => {
+                Vec::new()
+            }
             ThreeAddressCode::Return { src } => {
                 vec![*src]
             }
@@ -82,21 +194,91 @@ impl ThreeAddressCode {
             ThreeAddressCode::Label { .. } => {
                 Vec::new()
             }
-            ThreeAddressCode::Call { ret_reg: ret_val , args, .. } => {
-                let mut vregs = vec![*ret_val];
-                for r in args {
-                    vregs.push(*r);
-                }
-                vregs
+            ThreeAddressCode::Call { args, .. } => {
+                args.clone()
+            }
+            ThreeAddressCode::Fn { .. } => {
+                Vec::new()
+            }
+            ThreeAddressCode::Phi { args, .. } => {
+                args.iter().map(|(_, src)| *src).collect()
+            }
+            ThreeAddressCode::Loc { .. } => {
+                Vec::new()
+            }
+            // 忘れてバグの原因になるためワイルドカードを使わない
+        }
+    }
+
+    /// 命令が値を書き込むレジスタを`f`で置き換える
+    pub(crate) fn map_def_regs(&mut self, mut f: impl FnMut(VirtualReg) -> VirtualReg) {
+        match self {
+            ThreeAddressCode::LoadImm { dest, .. } |
+            ThreeAddressCode::BinOpCode { dest, .. } |
+            ThreeAddressCode::Assign { dest, .. } |
+            ThreeAddressCode::AddrOf { addr: dest, .. } |
+            ThreeAddressCode::LoadVar { value: dest, .. } |
+            ThreeAddressCode::GlobalAddr { dest, .. } |
+            ThreeAddressCode::LoadGlobal { dest, .. } |
+            ThreeAddressCode::StrAddr { dest, .. } |
+            ThreeAddressCode::Call { ret_reg: dest, .. } |
+            ThreeAddressCode::Phi { dest, .. } => {
+                *dest = f(*dest);
             }
             ThreeAddressCode::Fn { params, .. } => {
-                let mut vregs = Vec::new();
                 for param in params {
-                    vregs.push(param.dest);
+                    param.dest = f(param.dest);
                 }
-                vregs
             }
-            // 忘れてバグの原因になるためワイルドカードを使わない
+            ThreeAddressCode::EvalVar { .. } |
+            ThreeAddressCode::Store { .. } |
+            ThreeAddressCode::StoreGlobal { .. } |
+            ThreeAddressCode::Return { .. } |
+            ThreeAddressCode::IfFalse { .. } |
+            ThreeAddressCode::GoTo { .. } |
+            ThreeAddressCode::Label { .. } |
+            ThreeAddressCode::Loc { .. } => (),
         }
     }
-}
\ No newline at end of file
+
+    /// 命令が値を読むレジスタを`f`で置き換える
+    pub(crate) fn map_use_regs(&mut self, mut f: impl FnMut(VirtualReg) -> VirtualReg) {
+        match self {
+            ThreeAddressCode::BinOpCode { left, right, .. } => {
+                *left = f(*left);
+                *right = f(*right);
+            }
+            ThreeAddressCode::Store { addr, src, .. } => {
+                *addr = f(*addr);
+                *src = f(*src);
+            }
+            ThreeAddressCode::Assign { src, .. } |
+            ThreeAddressCode::EvalVar { dest: src, .. } |
+            ThreeAddressCode::AddrOf { var: src, .. } |
+            ThreeAddressCode::LoadVar { addr: src, .. } |
+            ThreeAddressCode::StoreGlobal { src, .. } |
+            ThreeAddressCode::Return { src } |
+            ThreeAddressCode::IfFalse { cond: src, .. } => {
+                *src = f(*src);
+            }
+            ThreeAddressCode::Call { args, .. } => {
+                for arg in args {
+                    *arg = f(*arg);
+                }
+            }
+            ThreeAddressCode::Phi { args, .. } => {
+                for (_, src) in args {
+                    *src = f(*src);
+                }
+            }
+            ThreeAddressCode::LoadImm { .. } |
+            ThreeAddressCode::GlobalAddr { .. } |
+            ThreeAddressCode::LoadGlobal { .. } |
+            ThreeAddressCode::StrAddr { .. } |
+            ThreeAddressCode::GoTo { .. } |
+            ThreeAddressCode::Label { .. } |
+            ThreeAddressCode::Fn { .. } |
+            ThreeAddressCode::Loc { .. } => (),
+        }
+    }
+}
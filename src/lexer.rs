@@ -1,9 +1,5 @@
-use std::usize;
-
-use anyhow::anyhow;
-
-use crate::types::{ Token, TokenKind::{self, *}, TypeKind };
-use crate::error_at;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::types::{ Span, Token, TokenKind::{self, *}, TypeKind };
 
 pub struct Tokenizer<'a> {
     input: &'a str,
@@ -30,7 +26,7 @@ impl<'a> Tokenizer<'a> {
     
     /// 入力のインデックスはトークンの構成文字か調べる
     fn is_alnum(&self, idx: usize) -> bool {
-        let maybe_c = self.input.chars().nth(idx);
+        let maybe_c = self.input.get(idx..).and_then(|s| s.chars().next());
         match maybe_c {
             None => false,
             Some(c) => c.is_ascii_alphanumeric() || c == '_'
@@ -38,19 +34,106 @@ impl<'a> Tokenizer<'a> {
     }
     
     /// 次に文字があるか確認する
+    /// - `pos`はバイト単位なので文字列リテラル中の非ASCII文字も読める
     fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.pos)
+        self.input.get(self.pos..).and_then(|s| s.chars().next())
     }
     
     /// 現在の要素を返して1文字を進める
     fn next(&mut self) -> Option<char> {
-        let next = self.input.chars().nth(self.pos);
-        self.pos += 1;
+        let next = self.peek();
+        self.pos += next.map_or(1, |c| c.len_utf8());
         next
     }
+
+    /// 文字列・文字リテラルの中身を1文字読んでバイト列に追加する
+    /// - `\`から始まるエスケープシーケンスを展開する
+    /// - `\ooo`は8進数で3桁まで, `\xhh`は16進数で何桁でも読み, 下位8bitを使う
+    fn read_literal_char(&mut self, bytes: &mut Vec<u8>) -> Result<(), Diagnostic> {
+        let head_pos = self.pos;
+        let c = self.next().unwrap();
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            return Ok(());
+        }
+
+        let Some(c) = self.next() else {
+            let span = Span { start: head_pos, end: self.pos };
+            return Err(Diagnostic::error(ErrorCode::UnterminatedLiteral, "リテラルが閉じられていません", span));
+        };
+        let byte = match c {
+            '0'..='7' => {
+                let mut val = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.peek().and_then(|n| n.to_digit(8)) {
+                        Some(d) => {
+                            val = val * 8 + d;
+                            self.next();
+                        }
+                        None => break,
+                    }
+                }
+                val as u8
+            }
+            'x' => {
+                let mut val: u32 = 0;
+                let mut digits = 0;
+                while let Some(d) = self.peek().and_then(|n| n.to_digit(16)) {
+                    val = val.wrapping_mul(16).wrapping_add(d);
+                    digits += 1;
+                    self.next();
+                }
+                if digits == 0 {
+                    let span = Span { start: head_pos, end: self.pos };
+                    return Err(Diagnostic::error(ErrorCode::InvalidEscape, "\\xの後に16進数がありません", span));
+                }
+                val as u8
+            }
+            'a' => 0x07,
+            'b' => 0x08,
+            't' => b'\t',
+            'n' => b'\n',
+            'v' => 0x0b,
+            'f' => 0x0c,
+            'r' => b'\r',
+            'e' => 0x1b,
+            // `\\`, `\"`, `\'`などはその文字自身
+            _ => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                return Ok(());
+            }
+        };
+        bytes.push(byte);
+        Ok(())
+    }
+
+    /// `quote`で囲まれたリテラルを読んでバイト列を返す
+    /// - 改行や入力の終わりまでに閉じられていなければエラー
+    fn read_literal(&mut self, quote: char) -> Result<Vec<u8>, Diagnostic> {
+        let head_pos = self.pos;
+        self.next();
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => {
+                    self.next();
+                    return Ok(bytes);
+                }
+                None | Some('\n') => {
+                    let span = Span { start: head_pos, end: self.pos };
+                    let msg = if quote == '"' { "文字列リテラルが閉じられていません" } else { "文字リテラルが閉じられていません" };
+                    return Err(Diagnostic::error(ErrorCode::UnterminatedLiteral, msg, span));
+                }
+                Some(_) => self.read_literal_char(&mut bytes)?,
+            }
+        }
+    }
     
     /// トークン化を行う
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    /// - トークナイズできない文字があればその位置を`Diagnostic`で返す
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Diagnostic> {
         let mut tok_vec = vec![];
 
         // 判定にcを使用
@@ -75,6 +158,42 @@ impl<'a> Tokenizer<'a> {
                 continue;
             }
 
+            // charをトークナイズする
+            // 次の文字も調べる必要がある
+            let len_char = "char".len();
+            if self.input.get(self.pos..).unwrap().starts_with("char") && !self.is_alnum(self.pos + len_char) {
+                let next = Token::new(TK_TYPE(TypeKind::Char), String::from("char"), len_char, self.pos);
+                self.pos += len_char;
+                
+                tok_vec.push(next);
+                
+                continue;
+            }
+
+            // structをトークナイズする
+            // 次の文字も調べる必要がある
+            let len_struct = "struct".len();
+            if self.input.get(self.pos..).unwrap().starts_with("struct") && !self.is_alnum(self.pos + len_struct) {
+                let next = Token::new(TK_TYPE(TypeKind::Struct), String::from("struct"), len_struct, self.pos);
+                self.pos += len_struct;
+                
+                tok_vec.push(next);
+                
+                continue;
+            }
+
+            // unionをトークナイズする
+            // 次の文字も調べる必要がある
+            let len_union = "union".len();
+            if self.input.get(self.pos..).unwrap().starts_with("union") && !self.is_alnum(self.pos + len_union) {
+                let next = Token::new(TK_TYPE(TypeKind::Union), String::from("union"), len_union, self.pos);
+                self.pos += len_union;
+                
+                tok_vec.push(next);
+                
+                continue;
+            }
+
             // forをトークナイズする
             // 次の文字も調べる必要がある
             let len_for = "for".len();
@@ -135,8 +254,59 @@ impl<'a> Tokenizer<'a> {
                 continue;
             }
 
+            // externをトークナイズする
+            // 次の文字も調べる必要がある
+            let len_extern = "extern".len();
+            if self.input.get(self.pos..).unwrap().starts_with("extern") && !self.is_alnum(self.pos + len_extern) {
+                let next = Token::new(TK_EXTERN, "extern".to_string(), len_extern, self.pos);
+                self.pos += len_extern;
+                
+                tok_vec.push(next);
+                
+                continue;
+            }
+
+            // 文字列リテラルをトークナイズする
+            if c == '"' {
+                let head_pos = self.pos;
+                let bytes = self.read_literal('"')?;
+                let len = self.pos - head_pos;
+                let next = Token::new(TK_STR(bytes), self.input[head_pos..self.pos].to_string(), len, head_pos);
+
+                tok_vec.push(next);
+
+                continue;
+            }
+
+            // 文字リテラルをトークナイズする
+            // 値はcharを符号付きで読んだintになる (`'\xff'`は-1)
+            if c == '\'' {
+                let head_pos = self.pos;
+                let bytes = self.read_literal('\'')?;
+                let len = self.pos - head_pos;
+                let mut next = Token::new(TK_NUM, self.input[head_pos..self.pos].to_string(), len, head_pos);
+                match bytes.first() {
+                    Some(&b) => next.val = Some(b as i8 as i32),
+                    None => return Err(Diagnostic::error(ErrorCode::EmptyCharLiteral, "空の文字リテラルです", next.span())),
+                }
+
+                tok_vec.push(next);
+
+                continue;
+            }
+
+            // 3文字の予約語をトークナイズする
+            if self.input.get(self.pos..).unwrap().starts_with("...") {
+                let next = Token::new(TK_RESERVED, String::from("..."), 3, self.pos);
+                self.pos += 3;
+
+                tok_vec.push(next);
+
+                continue;
+            }
+
             // 2文字の予約語をトークナイズする
-            let patterns_len_2 = ["<=", ">=", "==", "!="];
+            let patterns_len_2 = ["<=", ">=", "==", "!=", "->"];
             if let Some(pat) = self.starts_with_in(&patterns_len_2) {
                 // posは先頭を保存したいので先にTokenを作る
                 let next = Token::new(TK_RESERVED, pat.to_string(), 2, self.pos);
@@ -148,7 +318,7 @@ impl<'a> Tokenizer<'a> {
             }
 
             // 1文字の予約語をトークナイズする
-            let patterns_1 = ["+", "-", "*", "/", "(", ")", ";", "<", ">", "=", "{", "}", ",", "&"];
+            let patterns_1 = ["+", "-", "*", "/", "(", ")", ";", "<", ">", "=", "{", "}", "[", "]", ",", "&", "."];
             if let Some(pat) = self.starts_with_in(&patterns_1) {
                 // posは先頭を保存したいので先にTokenを作る
                 let next = Token::new(TK_RESERVED, pat.to_string(), 1, self.pos);
@@ -175,7 +345,10 @@ impl<'a> Tokenizer<'a> {
 
                 let mut next = Token::new(TK_NUM, number.clone(), number.len(), head_pos);
                 // 数字を設定する
-                next.val = Some(number.parse::<i32>().unwrap());
+                match number.parse::<i32>() {
+                    Ok(val) => next.val = Some(val),
+                    Err(_) => return Err(Diagnostic::error(ErrorCode::NumberTooLarge, "数値が大きすぎます", next.span())),
+                }
 
                 tok_vec.push(next);
                 
@@ -183,11 +356,11 @@ impl<'a> Tokenizer<'a> {
             }
 
             // 変数をトークナイズする
-            if 'A' <= c && c <= 'z' {
+            if ('A'..='z').contains(&c) {
                 let head_pos = self.pos;
                 let mut ident = self.next().unwrap().to_string();
                 
-                while let Some(_) = self.peek() {
+                while self.peek().is_some() {
                     if self.is_alnum(self.pos) {
                         ident.push(self.next().unwrap());
                     } else {
@@ -205,16 +378,15 @@ impl<'a> Tokenizer<'a> {
             
             // それ以外はエラーを出す
             else {
-                eprintln!("Error While Tokenizing");
-                let e = anyhow!("トークナイズできません: {}", c);
-                error_at(self.input, self.pos, e);
+                let span = Span { start: self.pos, end: self.pos + c.len_utf8() };
+                return Err(Diagnostic::error(ErrorCode::InvalidChar, format!("トークナイズできません: {}", c), span));
             };
         }
 
         let eof = Token::new(TK_EOF, String::from("<EOF>"), 1, self.pos);
         tok_vec.push(eof);
 
-        tok_vec
+        Ok(tok_vec)
 
     }
 }
@@ -243,13 +415,9 @@ impl<'a> TokenStream<'a> {
     pub(crate) fn consume_type(&mut self, typekind: TypeKind) -> bool {
         let tok = self.tok_vec.get(self.idx).unwrap();
         match &tok.kind {
-            TK_TYPE(ty) => {
-                if *ty == typekind {
-                    self.idx += 1;
-                    true
-                } else {
-                    false
-                }
+            TK_TYPE(ty) if *ty == typekind => {
+                self.idx += 1;
+                true
             }
             _ => false
         }
@@ -290,16 +458,28 @@ impl<'a> TokenStream<'a> {
         }
     }
 
-    pub(crate) fn expect(&mut self, op: &str) -> anyhow::Result<()> {
+    /// 文字列リテラルならその中身を返す
+    pub(crate) fn consume_str(&mut self) -> Option<Vec<u8>> {
+        let tok = self.get_current_token();
+        if let TK_STR(bytes) = tok.kind {
+            self.idx += 1;
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn expect(&mut self, op: &str) -> Result<(), Diagnostic> {
         let tok = self.tok_vec.get(self.idx).unwrap();
         let len = op.len();
         if tok.kind != TK_RESERVED || 
            tok.str.get(..len) != Some(op) || 
            tok.len != len {
             if op == ";" {
-                Err(anyhow!("';'が必要です"))
+                Err(Diagnostic::error(ErrorCode::ExpectedToken, "';'が必要です", tok.span()))
             } else {
-                Err(anyhow!("'{}'を想定していたが、'{}'が入力されました", op, tok.str))
+                let msg = format!("'{}'を想定していたが、'{}'が入力されました", op, tok.str);
+                Err(Diagnostic::error(ErrorCode::ExpectedToken, msg, tok.span()))
             }
         } else {
             self.idx += 1;
@@ -307,17 +487,17 @@ impl<'a> TokenStream<'a> {
         }
     }
 
-    pub(crate) fn expect_number(&mut self) -> anyhow::Result<i32> {
+    pub(crate) fn expect_number(&mut self) -> Result<i32, Diagnostic> {
         let tok = self.tok_vec.get(self.idx).unwrap();
         if tok.kind != TK_NUM {
-            Err(anyhow!("Error: ここは直前に数字が必要です"))
+            Err(Diagnostic::error(ErrorCode::ExpectedNumber, "ここは直前に数字が必要です", tok.span()))
         } else {
             match tok.val {
                 Some(val) => {
                     self.idx += 1;
                     Ok(val)
                 },
-                None => Err(anyhow!("Error: 'Token'に数字が格納されていません"))
+                None => Err(Diagnostic::error(ErrorCode::Internal, "'Token'に数字が格納されていません", tok.span()))
             }
         }
     }
@@ -327,4 +507,13 @@ impl<'a> TokenStream<'a> {
         let current_idx = self.idx;
         self.tok_vec[current_idx].clone()
     }
+
+    /// 直前に読んだトークンの終わりの位置
+    /// - ノードの範囲の終わりに使う
+    pub(crate) fn prev_end(&self) -> usize {
+        match self.idx.checked_sub(1) {
+            Some(prev) => self.tok_vec[prev].span().end,
+            None => 0,
+        }
+    }
 }
\ No newline at end of file
@@ -1,17 +1,166 @@
-use std::process::exit;
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
 
 pub mod types;
+pub mod diagnostic;
+pub mod source_map;
 pub mod parser;
+pub mod sema;
 pub mod lexer;
 pub mod ir;
 pub mod reg_alloc;
 pub mod gen_x86_64;
 pub mod frame;
+pub mod opt;
+pub mod driver;
+
+use crate::diagnostic::Diagnostics;
+use crate::frame::Frame;
+use crate::gen_x86_64::{ Generator, gen_globals, gen_strings };
+use crate::ir::gen_ir::{ GenIrContext, stmt_to_ir };
+use crate::lexer::{ Tokenizer, TokenStream };
+use crate::opt::pass_manager::PassManager;
+use crate::parser::Parser;
+use crate::source_map::SourceMap;
+use crate::reg_alloc::{ interval_analysis, register_allocation };
+use crate::types::{ StmtKind, Type };
+
+/// コンパイルの設定
+#[derive(Default)]
+pub struct Options {
+    /// 最適化パイプライン (既定では最適化しない)
+    pub pass_manager: PassManager,
+    /// 途中経過を標準エラー出力に書き出す
+    pub debug: bool,
+    /// 行番号のデバッグ情報 (`.file`, `.loc`) を出力する
+    pub debug_info: bool,
+}
+
+/// ソースコードをアセンブリにする
+/// - 最適化はしない
+pub fn compile(input: &str) -> Result<String> {
+    compile_with(input, &Options::default())
+}
+
+/// 設定を指定してソースコードをアセンブリにする
+pub fn compile_with(input: &str, options: &Options) -> Result<String> {
+    let mut out = Vec::new();
+    compile_to(input, options, &mut out)?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// 設定を指定してソースコードをアセンブリにし, `out`に書き出す
+/// - 字句解析・構文解析・型検査のエラーは`Diagnostics`として返す (`downcast_ref`で取り出せる)
+pub fn compile_to(input: &str, options: &Options, out: &mut dyn Write) -> Result<()> {
+    compile_file_to("<input>", input, options, out)
+}
+
+/// ファイル名を指定してソースコードをアセンブリにする
+/// - ファイル名はデバッグ情報に使う
+pub fn compile_file(name: &str, input: &str, options: &Options) -> Result<String> {
+    let mut out = Vec::new();
+    compile_file_to(name, input, options, &mut out)?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// ファイル名を指定してソースコードをアセンブリにし, `out`に書き出す
+pub fn compile_file_to(name: &str, input: &str, options: &Options, out: &mut dyn Write) -> Result<()> {
+    // トークナイズ
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().map_err(Diagnostics::from)?;
+
+    if options.debug {
+        eprintln!("[DEBUG] tokens: \n{:?}", tok_vec);
+    }
+
+    // パース
+    // エラーがあっても回復して全てのエラーを集める
+    let mut parser = Parser::new(TokenStream::new(tok_vec, input));
+    let mut nodes = parser.program().map_err(Diagnostics)?;
+
+    // 型検査
+    let symbols = sema::check(&mut nodes).map_err(Diagnostics)?;
+
+    if options.debug {
+        eprintln!("[DEBUG] symbols: \n{:?}", symbols);
+    }
+
+    if options.debug {
+        eprintln!("[DEBUG] node: \n{:?}", nodes);
+    }
+
+    // コード生成ここから
+    writeln!(out, ".intel_syntax noprefix")?;
+    let source_map = SourceMap::new(name, input);
+    if options.debug_info {
+        writeln!(out, ".file 1 \"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))?;
+    }
+
+    // caller-saved (呼び出し側が保存するレジスタ) だけを使用
+    let regs = vec!["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+    let regs_count = regs.len();
+
+    // 各関数について中間表現を生成してレジスタ割り当て
+    // TODO: ラベルのカウントが引き継がれていないため手動で引き継いでいる
+    let mut label_count = 0;
+    let mut strings = Vec::new();
+    let globals: HashMap<String, Type> = symbols.globals.iter()
+        .map(|g| (g.name.clone(), g.ty.clone()))
+        .collect();
+    for node in &nodes {
+        // グローバル変数は最後にまとめて出力する
+        // プロトタイプ宣言は何も出力しない
+        if let StmtKind::GlobalVar { .. } | StmtKind::FnDecl { .. } = node.kind {
+            continue;
+        }
+        let mut context = GenIrContext::new();
+        context.globals = globals.clone();
+        context.label_count = label_count;
+        context.strings = std::mem::take(&mut strings);
+        context.debug_info = options.debug_info;
+        stmt_to_ir(node, &mut context);
+        let code = context.get_ir_code();
+        let lvar_map = context.get_lvar_map();
+        let var_types = context.get_var_types();
+
+        // 最適化
+        let (code, lvar_map) = options.pass_manager.run(code, lvar_map);
+
+        // デバッグ
+        if options.debug {
+            eprintln!("[DEBUG] IR:");
+            for c in &code {
+                eprintln!("{:?}", c);
+            }
+        }
+
+        // スタックフレームの計算
+        let mut frame = Frame::with_types(lvar_map, &var_types);
+
+        // レジスタ割り当て
+        // スピルした値はフレームに領域を確保する
+        let mut intervals = interval_analysis::scan_interval(&code);
+        let vreg_to_loc = register_allocation::linear_reg_alloc(&mut intervals, regs_count, &mut frame);
+
+        // コード生成
+        let mut generator = Generator::new(regs.clone(), code, frame, out);
+        if options.debug_info {
+            generator.set_source_map(&source_map);
+        }
+        generator.gen_fn(vreg_to_loc.clone())?;
+
+        if options.debug {
+            eprintln!("[DEBUG] vreg_to_offset: {:?}", generator.frame.vreg_to_offset);
+            eprintln!("[DEBUG] vreg_to_loc");
+            eprintln!("{:?}", vreg_to_loc);
+        }
+        label_count = context.label_count;
+        strings = context.strings;
+    }
 
-pub fn error_at(input: &str, pos: usize, e: anyhow::Error) -> ! {
-    eprintln!("{}", input);
-    eprint!("{}", " ".repeat(pos));
-    eprint!("^ ");
-    eprintln!("{}", e);
-    exit(1);
+    gen_globals(&symbols.globals, out)?;
+    gen_strings(&strings, out)?;
+    Ok(())
 }
@@ -1,107 +1,208 @@
 #![allow(non_camel_case_types)]
 
-use clap::Parser as ClapParser;
-use anyhow::anyhow;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
 
-use nonicc::error_at;
-use nonicc::frame::Frame;
-use nonicc::lexer::{ Tokenizer, TokenStream };
-use nonicc::parser::{ Parser };
+use clap::{Parser as ClapParser, ValueEnum};
+use anyhow::{anyhow, bail, Context};
+
+use nonicc::Options;
+use nonicc::diagnostic::Diagnostics;
+use nonicc::source_map::SourceMap;
+use nonicc::opt::pass_manager::PassManager;
+use nonicc::driver::{self, AsmOutput, InputKind, TempFiles};
 
 #[derive(ClapParser, Debug)]
 struct Args {
-    #[arg(index = 1)]
-    input: String,
+    /// 入力ファイル (.c, .s, .o) ("-"のときは標準入力から読む)
+    #[arg(index = 1, required_unless_present = "expr", conflicts_with = "expr")]
+    inputs: Vec<String>,
+
+    /// ソースコードを文字列で直接渡す
+    #[arg(long = "expr")]
+    expr: Option<String>,
+
+    /// 出力ファイル
+    #[arg(short = 'o')]
+    output: Option<String>,
+
+    /// アセンブリを出力して終わる
+    #[arg(short = 'S', conflicts_with = "compile_only")]
+    asm_only: bool,
+
+    /// オブジェクトファイルを出力して終わる
+    #[arg(short = 'c')]
+    compile_only: bool,
 
     #[arg(short = 'd', long = "debug")]
     debug: bool,
+
+    /// 行番号のデバッグ情報を出力する
+    #[arg(short = 'g')]
+    debug_info: bool,
+
+    /// 最適化レベル (-O0, -O1, -O2)
+    #[arg(short = 'O', default_value_t = 0)]
+    opt_level: u8,
+
+    /// 実行する最適化パスを順に指定する (-Oより優先)
+    #[arg(long = "passes", value_delimiter = ',')]
+    passes: Option<Vec<String>>,
+
+    /// 指定したパスの後に中間表現を書き出す
+    #[arg(long = "print-after")]
+    print_after: Option<String>,
+
+    /// エラーの表示形式
+    #[arg(long = "diagnostics-format", value_enum, default_value_t = DiagnosticsFormat::Human)]
+    diagnostics_format: DiagnosticsFormat,
+}
+
+/// エラーの表示形式
+/// - `human`: `file.c:行:列: error: ...`と該当する行
+/// - `json`: 1行に1つのJSONオブジェクト
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiagnosticsFormat {
+    Human,
+    Json,
 }
 
 fn main() {
     // 引数を解析する
     let args = Args::parse();
-    let input = args.input;
-    
-    // トークナイズ
-    let mut tokenizer = Tokenizer::new(&input);
-    let tok_vec = tokenizer.tokenize();
-
-    if args.debug {
-        eprintln!("[DEBUG] tokens: \n{:?}", tok_vec);
+    if let Err(e) = run(&args) {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+/// 入力をコンパイルし, 指定された段階まで進める
+/// - `-S`: アセンブリ
+/// - `-c`: オブジェクトファイル (`as`)
+/// - 指定なし: 実行ファイル (`cc`)
+fn run(args: &Args) -> anyhow::Result<()> {
+    // 最適化パイプライン
+    let mut pass_manager = match &args.passes {
+        Some(names) => PassManager::with_passes(names)?,
+        None => PassManager::with_opt_level(args.opt_level),
+    };
+    if let Some(name) = &args.print_after {
+        pass_manager.set_print_after(name)?;
+    }
+    let options = Options { pass_manager, debug: args.debug, debug_info: args.debug_info };
+
+    // --exprは標準入力と同じように名前の無い入力として扱う
+    let inputs = match &args.expr {
+        Some(_) => vec!["-".to_string()],
+        None => args.inputs.clone(),
+    };
+    if args.output.is_some() && (args.asm_only || args.compile_only) && inputs.len() > 1 {
+        bail!("Cannot specify '-o' with '-S' or '-c' with multiple input files");
     }
 
-    // パース
-    let mut parser = Parser::new(TokenStream::new(tok_vec, &input));
-    let mut nodes = Vec::new();
-    let mut last_idx = parser.tokens.idx;
-    while !parser.tokens.is_eof()  {
+    // 一時ファイルを作る前に全てコンパイルしておく
+    // 途中でエラーになったときに一時ファイルを残さないため
+    let mut units = Vec::new();
+    for input in &inputs {
+        let kind = InputKind::from_path(input);
+        let asm = match kind {
+            InputKind::Source => Some(compile_source(args, input, &read_source(args, input)?, &options)?),
+            InputKind::Asm if args.asm_only => bail!("'{}' is already assembly", input),
+            InputKind::Object if args.asm_only || args.compile_only => bail!("'{}' is already an object file", input),
+            InputKind::Asm | InputKind::Object => None,
+        };
+        units.push((input, kind, asm));
+    }
 
-        nodes.push(parser.defun());
-        if args.debug {
-            eprintln!("[DEBUG] lvars: \n{:?}", parser.lvars);
+    let mut temps = TempFiles::new();
+    let mut objs = Vec::new();
+    for (input, kind, asm) in units {
+        if args.asm_only {
+            let asm = asm.expect("source should be compiled");
+            match AsmOutput::new(args.output.as_deref(), input) {
+                AsmOutput::Stdout => io::stdout().write_all(asm.as_bytes())?,
+                AsmOutput::File(path) => {
+                    std::fs::write(&path, asm).with_context(|| format!("Failed to write '{}'", path.display()))?;
+                }
+            }
+            continue;
         }
 
-        if parser.tokens.idx == last_idx {
-            // トークンが進まないときはエラーを出す
-            // 無限ループを避けるため
-            let e = anyhow!("Parser stuck: token index not advancing");
-            error_at(&input, parser.tokens.idx, e)
-        }
-        last_idx = parser.tokens.idx;
+        let asm_path = match (kind, asm) {
+            (InputKind::Object, _) => {
+                objs.push(PathBuf::from(input));
+                continue;
+            }
+            (InputKind::Asm, _) => PathBuf::from(input),
+            (InputKind::Source, asm) => {
+                let path = temps.path("s");
+                std::fs::write(&path, asm.expect("source should be compiled"))?;
+                path
+            }
+        };
+        let obj_path = if args.compile_only {
+            output_or(&args.output, input, "o")
+        } else {
+            temps.path("o")
+        };
+        driver::assemble(&asm_path, &obj_path)?;
+        objs.push(obj_path);
     }
 
-    if args.debug {
-        eprintln!("[DEBUG] node: \n{:?}", nodes);
+    if !args.asm_only && !args.compile_only {
+        let exe = args.output.clone().unwrap_or("a.out".to_string());
+        driver::link(&objs, &PathBuf::from(exe))?;
     }
+    Ok(())
+}
 
+/// `-o`があればそれを, 無ければ入力から決めた出力ファイル名を返す
+fn output_or(output: &Option<String>, input: &str, ext: &str) -> PathBuf {
+    match output {
+        Some(path) => PathBuf::from(path),
+        None => driver::output_path(input, ext),
+    }
+}
 
-    // コード生成ここから
-    println!(".intel_syntax noprefix");
-    println!(".globl main");
-
-    // 中間表現の生成
-    use nonicc::ir::gen_ir::{ GenIrContext, stmt_to_ir };
-    use nonicc::reg_alloc::{interval_analysis, register_allocation};
-    use nonicc::gen_x86_64;
-
-    // caller-saved (呼び出し側が保存するレジスタ) だけを使用
-    let regs = vec!["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-    let regs_count = regs.len();
-
-    // 各関数について中間表現を生成してレジスタ割り当て
-    // TODO: ラベルのカウントが引き継がれていないため手動で引き継いでいる
-    let mut label_count = 0;
-    for node in &nodes {
-        let mut context = GenIrContext::new();
-        context.label_count = label_count;
-        stmt_to_ir(node, &mut context);
-        let code = context.get_ir_code();
-        let lvar_map = context.get_lvar_map();
-        
-        // デバッグ
-        if args.debug {
-            eprintln!("[DEBUG] IR:");
-            for c in &code {
-                eprintln!("{:?}", c);
+/// ソースコードをコンパイルする
+/// - 字句解析・構文解析のエラーは`--diagnostics-format`の形式で表示用にする
+fn compile_source(args: &Args, input: &str, source: &str, options: &Options) -> anyhow::Result<String> {
+    let name = source_name(args, input);
+    nonicc::compile_file(&name, source, options).map_err(|e| match e.downcast_ref::<Diagnostics>() {
+        Some(d) => {
+            let map = SourceMap::new(&name, source);
+            match args.diagnostics_format {
+                DiagnosticsFormat::Human => anyhow!(d.render(&map)),
+                DiagnosticsFormat::Json => anyhow!(d.to_json(&map)),
             }
         }
+        None => e,
+    })
+}
 
-        // レジスタ割り当て
-        let mut intervals = interval_analysis::scan_interval(&code);
-        let vreg_to_reg = register_allocation::linear_reg_alloc(&mut intervals, regs_count);
-        
-        // スタックフレームの計算
-        let frame = Frame::from_lvar_map(lvar_map);
-
-        // コード生成
-        let generator = gen_x86_64::Generator::new(regs.clone(), code, frame);
-        generator.gen_fn(vreg_to_reg.clone());
-
-        if args.debug {
-            eprintln!("[DEBUG] vreg_to_offset: {:?}", generator.frame.vreg_to_offset);
-            eprintln!("[DEBUG] vreg_to_reg");
-            eprintln!("{:?}", vreg_to_reg);
+/// エラー表示に使う入力の名前
+fn source_name(args: &Args, input: &str) -> String {
+    match (&args.expr, input) {
+        (Some(_), _) => "<expr>".to_string(),
+        (None, "-") => "<stdin>".to_string(),
+        (None, path) => path.to_string(),
+    }
+}
+
+/// ソースコードを読み込む
+/// - `--expr`で渡された文字列, 標準入力, ファイルのいずれか
+fn read_source(args: &Args, input: &str) -> anyhow::Result<String> {
+    if let Some(expr) = &args.expr {
+        return Ok(expr.clone());
+    }
+    match input {
+        "-" => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).context("Failed to read from stdin")?;
+            Ok(source)
+        }
+        path => {
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path))
         }
-        label_count = context.label_count;
     }
 }
@@ -0,0 +1,3 @@
+pub mod const_prop;
+pub mod dce;
+pub mod pass_manager;
\ No newline at end of file
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::cfg::Cfg;
+use crate::ir::types_ir::{BinOp, ThreeAddressCode as TAC, VirtualReg, Width};
+use crate::opt::pass_manager::{FnIr, Pass};
+
+/// 定数畳み込みと定数伝播のパス
+pub struct ConstProp;
+
+impl Pass for ConstProp {
+    fn name(&self) -> &'static str {
+        "const-prop"
+    }
+
+    fn run(&self, f: &mut FnIr) -> bool {
+        run(&mut f.cfg)
+    }
+}
+
+/// 定数畳み込みと定数伝播
+/// - 値が定数の`BinOpCode`, `Assign`, `Phi`を`LoadImm`に置き換える
+/// - 条件が定数の`IfFalse`は`GoTo`にするか取り除く
+/// - 定義が一つしかない仮想レジスタだけを定数として扱う (SSA形式なら全て)
+/// - 何か書き換えたら`true`を返す
+pub fn run(cfg: &mut Cfg) -> bool {
+    let mut changed_any = false;
+    loop {
+        // スタック上の変数はメモリ越しに書き換わるので定数として扱わない
+        let vars = cfg.stack_vars();
+        let single_def = collect_single_defs(cfg, &vars);
+
+        let mut consts: HashMap<VirtualReg, i32> = HashMap::new();
+        let mut changed = false;
+        let mut branch_folded = false;
+
+        // 定義が使用より先に来るように逆後順で見る
+        for b in cfg.reverse_post_order() {
+            let code = std::mem::take(&mut cfg.blocks[b].code);
+            let mut folded = Vec::new();
+            for tac in code {
+                let replaced = fold(&tac, &consts, &single_def);
+                let tac = match replaced {
+                    Some(Fold::Replace(new)) => {
+                        changed = true;
+                        branch_folded |= matches!(new, TAC::GoTo { .. });
+                        new
+                    }
+                    Some(Fold::Remove) => {
+                        changed = true;
+                        branch_folded = true;
+                        continue;
+                    }
+                    None => tac,
+                };
+                if let TAC::LoadImm { dest, value } = tac && single_def.contains(&dest) {
+                    consts.insert(dest, value);
+                }
+                folded.push(tac);
+            }
+            cfg.blocks[b].code = folded;
+        }
+
+        if branch_folded {
+            // 辺が変わるのでφ関数の引数と到達できないブロックを整理する
+            cfg.compute_edges();
+            cfg.remove_unreachable();
+        }
+
+        if !changed {
+            break;
+        }
+        changed_any = true;
+    }
+    changed_any
+}
+
+/// 命令の書き換え方
+enum Fold {
+    Replace(TAC),
+    Remove,
+}
+
+/// 命令を定数で畳み込めるなら書き換え方を返す
+fn fold(tac: &TAC, consts: &HashMap<VirtualReg, i32>, single_def: &HashSet<VirtualReg>) -> Option<Fold> {
+    match tac {
+        TAC::BinOpCode { dest, left, op, right, width } if single_def.contains(dest) => {
+            let l = consts.get(left)?;
+            let r = consts.get(right)?;
+            let value = match width {
+                Width::Byte | Width::Dword => eval(op, *l, *r)?,
+                Width::Qword => eval_qword(op, *l, *r)?,
+            };
+            Some(Fold::Replace(TAC::LoadImm { dest: *dest, value }))
+        }
+        TAC::Assign { dest, src } if single_def.contains(dest) => {
+            let value = consts.get(src)?;
+            Some(Fold::Replace(TAC::LoadImm { dest: *dest, value: *value }))
+        }
+        TAC::Phi { dest, args } => {
+            // 全ての引数が同じ定数なら合流しても定数
+            let (_, first) = args.first()?;
+            let value = consts.get(first)?;
+            for (_, src) in args {
+                if consts.get(src) != Some(value) {
+                    return None;
+                }
+            }
+            Some(Fold::Replace(TAC::LoadImm { dest: *dest, value: *value }))
+        }
+        TAC::IfFalse { cond, label } => {
+            let value = consts.get(cond)?;
+            if *value == 0 {
+                Some(Fold::Replace(TAC::GoTo { label: label.clone() }))
+            } else {
+                Some(Fold::Remove)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// 二項演算を32bitの符号付き整数として計算する
+/// - ゼロ除算などの未定義動作は畳み込まない
+fn eval(op: &BinOp, l: i32, r: i32) -> Option<i32> {
+    let value = match op {
+        BinOp::Add => l.wrapping_add(r),
+        BinOp::Sub => l.wrapping_sub(r),
+        BinOp::Mul => l.wrapping_mul(r),
+        BinOp::Div => l.checked_div(r)?,
+        BinOp::Le => (l <= r) as i32,
+        BinOp::Lt => (l < r) as i32,
+        BinOp::Eq => (l == r) as i32,
+        BinOp::Ne => (l != r) as i32,
+    };
+    Some(value)
+}
+
+/// 二項演算を64bitの符号付き整数として計算する
+/// - 結果が32bitに収まらないときは畳み込まない
+fn eval_qword(op: &BinOp, l: i32, r: i32) -> Option<i32> {
+    let (l, r) = (l as i64, r as i64);
+    let value = match op {
+        BinOp::Add => l.checked_add(r)?,
+        BinOp::Sub => l.checked_sub(r)?,
+        BinOp::Mul => l.checked_mul(r)?,
+        BinOp::Div => l.checked_div(r)?,
+        BinOp::Le => (l <= r) as i64,
+        BinOp::Lt => (l < r) as i64,
+        BinOp::Eq => (l == r) as i64,
+        BinOp::Ne => (l != r) as i64,
+    };
+    i32::try_from(value).ok()
+}
+
+/// 一度だけ定義される仮想レジスタを集める
+fn collect_single_defs(cfg: &Cfg, vars: &HashSet<VirtualReg>) -> HashSet<VirtualReg> {
+    let mut count: HashMap<VirtualReg, usize> = HashMap::new();
+    for tac in cfg.blocks.iter().flat_map(|b| b.code.iter()) {
+        for reg in tac.get_def_regs() {
+            *count.entry(reg).or_default() += 1;
+        }
+    }
+    count.into_iter()
+        .filter(|(reg, n)| *n == 1 && !vars.contains(reg))
+        .map(|(reg, _)| reg)
+        .collect()
+}
+
+#[test]
+/// 1 + 2 * 3 を畳み込む
+fn test_fold_binop() {
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 2 },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 3 },
+        TAC::BinOpCode { dest: VirtualReg { id: 3 }, left: VirtualReg { id: 1 }, op: BinOp::Mul, right: VirtualReg { id: 2 }, width: Width::Dword },
+        TAC::BinOpCode { dest: VirtualReg { id: 4 }, left: VirtualReg { id: 0 }, op: BinOp::Add, right: VirtualReg { id: 3 }, width: Width::Dword },
+        TAC::Return { src: VirtualReg { id: 4 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(run(&mut cfg));
+
+    assert_eq!(cfg.to_code()[3..], [
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 6 },
+        TAC::LoadImm { dest: VirtualReg { id: 4 }, value: 7 },
+        TAC::Return { src: VirtualReg { id: 4 } },
+    ]);
+}
+
+#[test]
+/// ゼロ除算は畳み込まない
+fn test_fold_div_by_zero() {
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 0 },
+        TAC::BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 0 }, op: BinOp::Div, right: VirtualReg { id: 1 }, width: Width::Dword },
+        TAC::Return { src: VirtualReg { id: 2 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(!run(&mut cfg));
+    assert_eq!(cfg.to_code(), ir);
+}
+
+#[test]
+/// 条件が定数のif文は片方の枝だけが残る
+fn test_fold_if_false() {
+    use crate::ir::types_ir::Label;
+
+    // if (0) x = 1; else x = 2; return x;
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 },
+        TAC::IfFalse { cond: VirtualReg { id: 0 }, label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        TAC::GoTo { label: Label::Lend(1) },
+        TAC::Label { label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 2 },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Phi { dest: VirtualReg { id: 3 }, args: vec![(1, VirtualReg { id: 1 }), (2, VirtualReg { id: 2 })] },
+        TAC::Return { src: VirtualReg { id: 3 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(run(&mut cfg));
+
+    assert_eq!(cfg.to_code(), vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 },
+        TAC::GoTo { label: Label::Lelse(0) },
+        TAC::Label { label: Label::Lelse(0) },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 2 },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 2 },
+        TAC::Return { src: VirtualReg { id: 3 } },
+    ]);
+}
+
+#[test]
+/// アドレスを取られた変数は定数として扱わない
+fn test_no_fold_addr_taken() {
+    let a = VirtualReg { id: 0 };
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        TAC::Assign { dest: a, src: VirtualReg { id: 1 } },
+        TAC::AddrOf { addr: VirtualReg { id: 2 }, var: a },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 3 },
+        TAC::Store { addr: VirtualReg { id: 2 }, src: VirtualReg { id: 3 }, width: Width::Dword },
+        TAC::EvalVar { dest: a, name: "a".to_string() },
+        TAC::LoadImm { dest: VirtualReg { id: 4 }, value: 1 },
+        TAC::BinOpCode { dest: VirtualReg { id: 5 }, left: a, op: BinOp::Add, right: VirtualReg { id: 4 }, width: Width::Dword },
+        TAC::Return { src: VirtualReg { id: 5 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(!run(&mut cfg));
+    assert_eq!(cfg.to_code(), ir);
+}
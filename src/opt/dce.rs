@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::cfg::Cfg;
+use crate::ir::types_ir::{Label, ThreeAddressCode as TAC, VirtualReg};
+use crate::opt::pass_manager::{FnIr, Pass};
+
+/// 不要コード除去のパス
+pub struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&self, f: &mut FnIr) -> bool {
+        run(&mut f.cfg)
+    }
+}
+
+/// 不要コード除去
+/// - 入口から到達できないブロックを取り除く
+/// - 副作用が無く結果が使われない命令を取り除く
+/// - どこからも飛んでこないラベルを取り除く
+/// - 何か書き換えたら`true`を返す
+pub fn run(cfg: &mut Cfg) -> bool {
+    let len = cfg.blocks.len();
+    cfg.remove_unreachable();
+    let mut changed = cfg.blocks.len() != len;
+
+    changed |= remove_dead_code(cfg);
+    changed |= remove_unused_labels(cfg);
+    changed
+}
+
+/// 副作用のある命令から使われている値を遡って印を付け, 印の無い命令を消す
+/// - 使われない値同士で循環しているφ関数も消せる
+fn remove_dead_code(cfg: &mut Cfg) -> bool {
+    let vars = cfg.stack_vars();
+
+    let mut defs: HashMap<VirtualReg, Vec<(usize, usize)>> = HashMap::new();
+    let mut live: Vec<Vec<bool>> = Vec::new();
+    let mut worklist = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut block_live = Vec::new();
+        for (i, tac) in block.code.iter().enumerate() {
+            for reg in tac.get_def_regs() {
+                defs.entry(reg).or_default().push((b, i));
+            }
+            let critical = has_side_effect(tac, &vars);
+            if critical {
+                worklist.extend(tac.get_use_regs());
+            }
+            block_live.push(critical);
+        }
+        live.push(block_live);
+    }
+
+    let mut live_regs = HashSet::new();
+    while let Some(reg) = worklist.pop() {
+        if !live_regs.insert(reg) {
+            continue;
+        }
+        for &(b, i) in defs.get(&reg).into_iter().flatten() {
+            if !live[b][i] {
+                live[b][i] = true;
+                worklist.extend(cfg.blocks[b].code[i].get_use_regs());
+            }
+        }
+    }
+
+    let mut changed = false;
+    for (block, block_live) in cfg.blocks.iter_mut().zip(live) {
+        if block_live.iter().all(|l| *l) {
+            continue;
+        }
+        changed = true;
+        let code = std::mem::take(&mut block.code);
+        block.code = code.into_iter()
+            .zip(block_live)
+            .filter(|(_, l)| *l)
+            .map(|(tac, _)| tac)
+            .collect();
+    }
+    changed
+}
+
+/// 結果が使われなくても残す必要がある命令か調べる
+/// - スタック上の変数への代入はメモリ越しに読まれることがあるので残す
+fn has_side_effect(tac: &TAC, vars: &HashSet<VirtualReg>) -> bool {
+    match tac {
+        TAC::LoadImm { .. } => false,
+        TAC::BinOpCode { .. } => false,
+        TAC::Assign { dest, .. } => vars.contains(dest),
+        TAC::EvalVar { .. } => true,
+        TAC::AddrOf { .. } => false,
+        TAC::LoadVar { .. } => false,
+        TAC::Store { .. } => true,
+        TAC::GlobalAddr { .. } => false,
+        TAC::LoadGlobal { .. } => false,
+        TAC::StoreGlobal { .. } => true,
+        TAC::StrAddr { .. } => false,
+        TAC::Return { .. } => true,
+        TAC::IfFalse { .. } => true,
+        TAC::GoTo { .. } => true,
+        TAC::Label { .. } => true,
+        TAC::Call { .. } => true,
+        TAC::Fn { .. } => true,
+        TAC::Phi { .. } => false,
+        TAC::Loc { .. } => true,
+        // 忘れてバグの原因になるためワイルドカードを使わない
+    }
+}
+
+/// どこからも飛んでこないラベルを消す
+/// - ラベルの無いブロックには直前のブロックから落ちてくるだけなので辺は変わらない
+fn remove_unused_labels(cfg: &mut Cfg) -> bool {
+    let mut targets: HashSet<Label> = HashSet::new();
+    for tac in cfg.blocks.iter().flat_map(|b| b.code.iter()) {
+        if let TAC::GoTo { label } | TAC::IfFalse { label, .. } = tac {
+            targets.insert(label.clone());
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut cfg.blocks {
+        if let Some(label) = block.label() && !targets.contains(label) {
+            block.code.remove(0);
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[test]
+/// 式文の結果は使われないので消える
+fn test_dce_expr_stmt() {
+    // 5; 4; return 0;
+    let ir = vec![
+        TAC::Fn { fn_name: "main".to_string(), params: Vec::new() },
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 5 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 4 },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 0 },
+        TAC::Return { src: VirtualReg { id: 2 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(run(&mut cfg));
+
+    assert_eq!(cfg.to_code(), vec![
+        TAC::Fn { fn_name: "main".to_string(), params: Vec::new() },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 0 },
+        TAC::Return { src: VirtualReg { id: 2 } },
+    ]);
+}
+
+#[test]
+/// returnの後ろの命令と使われないラベルは消える
+fn test_dce_after_return() {
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 1 },
+        TAC::Return { src: VirtualReg { id: 0 } },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 2 },
+        TAC::Return { src: VirtualReg { id: 1 } },
+        TAC::Label { label: Label::Lend(0) },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(run(&mut cfg));
+
+    assert_eq!(cfg.to_code(), ir[..2]);
+}
+
+#[test]
+/// 副作用のある命令が使う値は消さない
+fn test_dce_keep_side_effects() {
+    use crate::ir::types_ir::Width;
+    let a = VirtualReg { id: 0 };
+    let ir = vec![
+        TAC::AddrOf { addr: VirtualReg { id: 1 }, var: a },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 3 },
+        TAC::Store { addr: VirtualReg { id: 1 }, src: VirtualReg { id: 2 }, width: Width::Dword },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 1 },
+        TAC::Assign { dest: a, src: VirtualReg { id: 3 } },
+        TAC::Call { fn_name: "foo".to_string(), args: Vec::new(), ret_reg: VirtualReg { id: 4 }, width: Width::Dword },
+        TAC::LoadImm { dest: VirtualReg { id: 5 }, value: 0 },
+        TAC::Return { src: VirtualReg { id: 5 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(!run(&mut cfg));
+    assert_eq!(cfg.to_code(), ir);
+}
+
+#[test]
+/// ループの中で自分自身にしか使われないφ関数は消える
+fn test_dce_dead_phi_cycle() {
+    use crate::ir::types_ir::{BinOp, Width};
+
+    // 0: 入口, 1: ループの先頭, 2: 本体, 3: 出口
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 },
+        TAC::LoadImm { dest: VirtualReg { id: 5 }, value: 1 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::Phi { dest: VirtualReg { id: 1 }, args: vec![(0, VirtualReg { id: 0 }), (2, VirtualReg { id: 2 })] },
+        TAC::IfFalse { cond: VirtualReg { id: 5 }, label: Label::Lend(1) },
+        TAC::BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 1 }, op: BinOp::Add, right: VirtualReg { id: 5 }, width: Width::Dword },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 5 } },
+    ];
+    let mut cfg = Cfg::new(&ir);
+    assert!(run(&mut cfg));
+
+    let code = cfg.to_code();
+    assert!(!code.iter().any(|c| matches!(c, TAC::Phi { .. } | TAC::BinOpCode { .. })));
+    assert!(!code.contains(&TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 0 }));
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::ir::cfg::Cfg;
+use crate::ir::ssa;
+use crate::ir::types_ir::{ThreeAddressCode as TAC, VirtualReg};
+use crate::opt::const_prop::ConstProp;
+use crate::opt::dce::Dce;
+
+/// 最適化の対象になる関数ひとつ分の中間表現
+#[derive(Debug)]
+pub struct FnIr {
+    pub cfg: Cfg,
+    pub lvar_map: HashMap<String, VirtualReg>,
+}
+
+/// 中間表現を書き換える最適化パス
+/// - `run`は何か書き換えたら`true`を返す
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&self, f: &mut FnIr) -> bool;
+}
+
+/// 名前からパスを探す
+/// - `--passes`, `--print-after`で指定する名前
+pub fn pass_by_name(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "const-prop" => Some(Box::new(ConstProp)),
+        "dce" => Some(Box::new(Dce)),
+        _ => None,
+    }
+}
+
+/// 最適化パスを順に実行する
+/// - パスはSSA形式の`Cfg`に対して実行する
+/// - パスが一つも無いときはSSA形式にもしない
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    /// 変化が無くなるまでパイプライン全体を繰り返すか
+    until_fixpoint: bool,
+    /// 指定したパスの後に中間表現を標準エラー出力に書き出す
+    print_after: Option<String>,
+}
+
+impl PassManager {
+    /// 最適化レベルに対応するパイプラインを作る
+    /// - `-O0`: 何もしない
+    /// - `-O1`: 定数の畳み込み, 不要コード除去
+    /// - `-O2`: `-O1`を変化が無くなるまで繰り返す
+    pub fn with_opt_level(opt_level: u8) -> Self {
+        let names: &[&str] = match opt_level {
+            0 => &[],
+            _ => &["const-prop", "dce"],
+        };
+        let passes = names.iter().filter_map(|name| pass_by_name(name)).collect();
+        PassManager { passes, until_fixpoint: opt_level >= 2, print_after: None }
+    }
+
+    /// 名前で指定したパスを指定した順に実行する
+    pub fn with_passes(names: &[String]) -> Result<Self> {
+        let mut passes = Vec::new();
+        for name in names {
+            let pass = pass_by_name(name).ok_or_else(|| anyhow!("Unknown pass: '{}'", name))?;
+            passes.push(pass);
+        }
+        Ok(PassManager { passes, until_fixpoint: false, print_after: None })
+    }
+
+    /// 中間表現を書き出すパスを設定する
+    pub fn set_print_after(&mut self, name: &str) -> Result<()> {
+        if pass_by_name(name).is_none() {
+            return Err(anyhow!("Unknown pass: '{}'", name));
+        }
+        self.print_after = Some(name.to_string());
+        Ok(())
+    }
+
+    /// 関数ひとつ分の中間表現を最適化する
+    /// - スタックに置かなくて良くなった変数は`lvar_map`から取り除かれる
+    pub fn run(&self, code: Vec<TAC>, lvar_map: HashMap<String, VirtualReg>) -> (Vec<TAC>, HashMap<String, VirtualReg>) {
+        if self.passes.is_empty() {
+            return (code, lvar_map);
+        }
+
+        let mut f = FnIr { cfg: Cfg::new(&code), lvar_map };
+        ssa::construct(&mut f.cfg, &mut f.lvar_map);
+
+        loop {
+            let mut changed = false;
+            for pass in &self.passes {
+                changed |= pass.run(&mut f);
+                if self.print_after.as_deref() == Some(pass.name()) {
+                    print_ir(pass.name(), &f.cfg);
+                }
+            }
+            if !self.until_fixpoint || !changed {
+                break;
+            }
+        }
+
+        ssa::destruct(&mut f.cfg);
+        (f.cfg.to_code(), f.lvar_map)
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::with_opt_level(0)
+    }
+}
+
+/// 中間表現を標準エラー出力に書き出す
+fn print_ir(pass_name: &str, cfg: &Cfg) {
+    eprintln!("[IR after {}]", pass_name);
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        eprintln!("  block {}: preds={:?} succs={:?}", b, block.preds, block.succs);
+        for tac in &block.code {
+            eprintln!("    {:?}", tac);
+        }
+    }
+}
+
+#[test]
+/// -O0では命令列を変えない
+fn test_opt_level_0() {
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 5 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 0 },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ];
+    let pm = PassManager::with_opt_level(0);
+    let (code, _) = pm.run(ir.clone(), HashMap::new());
+    assert_eq!(code, ir);
+}
+
+#[test]
+/// 指定したパスだけを実行する
+fn test_with_passes() {
+    use crate::ir::types_ir::{BinOp, Width};
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 5 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        TAC::LoadImm { dest: VirtualReg { id: 2 }, value: 2 },
+        TAC::BinOpCode { dest: VirtualReg { id: 3 }, left: VirtualReg { id: 1 }, op: BinOp::Add, right: VirtualReg { id: 2 }, width: Width::Dword },
+        TAC::Return { src: VirtualReg { id: 3 } },
+    ];
+    let pm = PassManager::with_passes(&["dce".to_string()]).unwrap();
+    let (code, _) = pm.run(ir.clone(), HashMap::new());
+    assert_eq!(code, ir[1..]);
+
+    assert!(PassManager::with_passes(&["unknown".to_string()]).is_err());
+}
@@ -1,26 +1,49 @@
-use anyhow::anyhow;
+use std::collections::HashMap;
+use std::rc::Rc;
 
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::types::{
-    BinOp, 
-    Expr, 
-    Stmt, 
-    Type, 
-    TypeKind, 
-    TokenKind::{ 
-        TK_RETURN, 
-        TK_IF, 
-        TK_ELSE, 
-        TK_WHILE, 
-        TK_FOR 
+    Aggregate,
+    BinOp,
+    Expr,
+    ExprKind,
+    Span,
+    Stmt,
+    StmtKind,
+    Type,
+    TypeKind,
+    TokenKind::{
+        TK_RETURN,
+        TK_IF,
+        TK_ELSE,
+        TK_EXTERN,
+        TK_WHILE,
+        TK_FOR,
+        TK_TYPE,
+        TK_EOF
     }
 };
 use crate::lexer::TokenStream;
-use crate::error_at;
+
+/// パースの結果
+/// - 失敗したときは最初に見つかった問題を返す
+pub type PResult<T> = Result<T, Diagnostic>;
 
 pub struct Parser<'a> {
     pub tokens: TokenStream<'a>,
+    /// 定義済みの関数
     defined_fn: Vec<String>,
+    /// 呼び出せる関数 (定義済みとプロトタイプ宣言済み)
+    declared_fn: Vec<String>,
     pub lvars: Vec<String>,
+    /// 定義済みのグローバル変数
+    globals: Vec<String>,
+    /// `extern`で宣言されたグローバル変数
+    extern_globals: Vec<String>,
+    /// 宣言済みの構造体・共用体のタグ (定義前は不完全型)
+    tags: HashMap<String, Rc<Aggregate>>,
+    /// 回復して読み進めたエラー
+    errors: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
@@ -28,184 +51,572 @@ impl<'a> Parser<'a> {
         Parser {
             tokens,
             defined_fn: Vec::new(),
+            declared_fn: Vec::new(),
             lvars: Vec::new(),
+            globals: Vec::new(),
+            extern_globals: Vec::new(),
+            tags: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// `program = (defun | declaration)*`
+    /// - エラーがあっても次の関数定義まで読み飛ばして続ける
+    /// - 見つかった全てのエラーを返す
+    pub fn program(&mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let mut nodes = Vec::new();
+        let mut errors = Vec::new();
+        while !self.tokens.is_eof() {
+            let last_idx = self.tokens.idx;
+            if self.is_defun() {
+                match self.defun() {
+                    Ok(node) => nodes.push(node),
+                    Err(e) => errors.extend(e),
+                }
+            } else {
+                match self.declaration() {
+                    Ok(decls) => nodes.extend(decls),
+                    Err(e) => {
+                        errors.push(e);
+                        self.synchronize_toplevel();
+                    }
+                }
+            }
+            if self.tokens.idx == last_idx {
+                // トークンが進まないときはエラーを出す
+                // 無限ループを避けるため
+                errors.push(self.error_here(ErrorCode::Internal, "Parser stuck: token index not advancing"));
+                break;
+            }
+        }
+        if errors.is_empty() {
+            Ok(nodes)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// 関数定義か調べる (トークンは読み進めない)
+    /// - 型と名前と`( ... )`の次が`{`なら関数定義
+    /// - `extern`や構造体の定義で始まるときは宣言
+    /// - 型が無いときは関数定義としてエラーを報告させる
+    fn is_defun(&mut self) -> bool {
+        let idx = self.tokens.idx;
+        if self.tokens.consume_keyword(TK_EXTERN) {
+            self.tokens.idx = idx;
+            return false;
+        }
+        // 構造体の定義はメンバを読むと型が登録されるため, ここでは読まない
+        let is_struct_def = self.is_struct_def();
+        self.tokens.idx = idx;
+        if is_struct_def {
+            return false;
+        }
+        let is_defun = match self.base_type() {
+            Ok(Some(base)) => {
+                if self.declarator(base).is_ok() && self.tokens.consume("(") {
+                    // 引数の括弧を読み飛ばす
+                    while !self.tokens.is_eof() && !self.tokens.consume(")") {
+                        self.tokens.idx += 1;
+                    }
+                    self.tokens.consume("{")
+                } else {
+                    false
+                }
+            }
+            Ok(None) => true,
+            Err(_) => false,
+        };
+        self.tokens.idx = idx;
+        is_defun
+    }
+
+    /// `struct`, `union`の定義が始まるか調べる
+    /// - トークンは読み進めるので呼び出し側で戻す
+    fn is_struct_def(&mut self) -> bool {
+        if !self.tokens.consume_type(TypeKind::Struct) && !self.tokens.consume_type(TypeKind::Union) {
+            return false;
+        }
+        self.tokens.consume_ident();
+        self.tokens.consume("{")
+    }
+
+    /// `declaration = "extern"? base_type (init_declarator ("," init_declarator)*)? ";"`
+    /// `init_declarator = declarator ("(" param_types | "=" "-"? num)?`
+    /// - 名前の次が`(`なら関数のプロトタイプ宣言, それ以外はグローバル変数になる
+    /// - 構造体のタグの定義だけのときは何も返さない
+    fn declaration(&mut self) -> PResult<Vec<Stmt>> {
+        let start = self.current_pos();
+        let is_extern = self.tokens.consume_keyword(TK_EXTERN);
+        let Some(base) = self.base_type()? else {
+            return Err(self.error_here(ErrorCode::ExpectedType, "type specifier missing"));
+        };
+
+        let mut decls = Vec::new();
+        if self.tokens.consume(";") {
+            return Ok(decls);
+        }
+        loop {
+            // 2つ目以降の宣言は名前から始まる範囲にする
+            let name_pos = self.current_pos();
+            let decl_start = if decls.is_empty() { start } else { name_pos };
+            let (name, ty) = self.declarator(base.clone())?;
+            let kind = if self.tokens.consume("(") {
+                self.fn_decl(name, ty, name_pos)?
+            } else {
+                self.global_var(name, ty, is_extern, name_pos)?
+            };
+            decls.push(Stmt::new(kind, self.span_from(decl_start)));
+            if !self.tokens.consume(",") {
+                break;
+            }
+        }
+        self.tokens.expect(";")?;
+        Ok(decls)
+    }
+
+    /// 関数のプロトタイプ宣言を読む (`(`は読んだ後に呼ぶ)
+    /// - 同じ関数は何度宣言してもよい (型が合うかは`sema`で調べる)
+    fn fn_decl(&mut self, fn_name: String, ret: Type, name_pos: usize) -> PResult<StmtKind> {
+        // 引数の括弧の中で読み直しを始めないように先に読む
+        let (params, variadic) = self.param_types()?;
+        if ret != Type::Int {
+            let msg = format!("function return type '{}' is not supported", ret);
+            return Err(Diagnostic::error(ErrorCode::ExpectedType, msg, self.span_from(name_pos)));
+        }
+        if self.globals.contains(&fn_name) || self.extern_globals.contains(&fn_name) {
+            let msg = format!("redefinition of '{}' as different kind of symbol", fn_name);
+            return Err(Diagnostic::error(ErrorCode::Redefinition, msg, self.span_from(name_pos)));
+        }
+        if !self.declared_fn.contains(&fn_name) {
+            self.declared_fn.push(fn_name.clone());
+        }
+        Ok(StmtKind::FnDecl { fn_name, params, variadic })
+    }
+
+    /// グローバル変数の宣言を読む
+    /// - 初期値は整数の定数のみ
+    /// - `extern`宣言は初期値を持たず, 同じ名前の定義があってもよい
+    fn global_var(&mut self, name: String, ty: Type, is_extern: bool, name_pos: usize) -> PResult<StmtKind> {
+        if self.declared_fn.contains(&name) {
+            let msg = format!("redefinition of '{}' as different kind of symbol", name);
+            return Err(Diagnostic::error(ErrorCode::Redefinition, msg, self.span_from(name_pos)));
+        }
+        if !is_extern && self.globals.contains(&name) {
+            let msg = format!("redefinition of '{}'", name);
+            return Err(Diagnostic::error(ErrorCode::Redefinition, msg, self.span_from(name_pos)));
+        }
+
+        let init = if !is_extern && self.tokens.consume("=") {
+            let is_neg = self.tokens.consume("-");
+            let val = self.tokens.expect_number()?;
+            Some(if is_neg { val.wrapping_neg() } else { val })
+        } else {
+            None
+        };
+
+        if is_extern {
+            self.extern_globals.push(name.clone());
+        } else {
+            self.globals.push(name.clone());
+        }
+        Ok(StmtKind::GlobalVar { name, ty, init, is_extern })
+    }
+
+    /// 文の途中でエラーになったときに次の文の先頭まで読み飛ばす
+    /// - `;`は読んでから止まる
+    /// - `}`, `int`, EOFの手前で止まる
+    fn synchronize_stmt(&mut self) {
+        loop {
+            let tok = self.tokens.get_current_token();
+            match tok.kind {
+                TK_EOF | TK_TYPE(_) => return,
+                _ if tok.str == "}" => return,
+                _ if tok.str == ";" => {
+                    self.tokens.idx += 1;
+                    return;
+                }
+                _ => self.tokens.idx += 1,
+            }
+        }
+    }
+
+    /// 関数定義の途中でエラーになったときに次の関数定義の先頭まで読み飛ばす
+    /// - 括弧の外にある型か`extern`, EOFの手前で止まる
+    fn synchronize_toplevel(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            let tok = self.tokens.get_current_token();
+            match tok.kind {
+                TK_EOF => return,
+                TK_TYPE(_) | TK_EXTERN if depth == 0 => return,
+                _ if tok.str == "{" => depth += 1,
+                _ if tok.str == "}" => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            self.tokens.idx += 1;
         }
     }
-    
-    /// `params = "(" ident, .. ")"`
-    fn params(&mut self) -> Vec<Expr> {
-        self.tokens.expect("(").unwrap_or_else( |e|{
-            eprintln!("Error While Parsing");
-            error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-        });
+
+    /// `}`までの文を読む (`{`は読んだ後に呼ぶ)
+    /// - 文でエラーになったら記録して次の文から続ける
+    fn block_body(&mut self) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        while !self.tokens.consume("}") {
+            if self.tokens.is_eof() {
+                self.errors.push(self.error_here(ErrorCode::ExpectedToken, "'}'が必要です"));
+                break;
+            }
+            let start_idx = self.tokens.idx;
+            match self.stmt() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize_stmt();
+                    // 読み飛ばせなかったときは無限ループを避けるため1つ進める
+                    let tok = self.tokens.get_current_token();
+                    if self.tokens.idx == start_idx && tok.kind != TK_EOF && tok.str != "}" {
+                        self.tokens.idx += 1;
+                    }
+                }
+            }
+        }
+        stmts
+    }
+
+    /// 現在のトークンの位置でエラーを作る
+    fn error_here(&self, code: ErrorCode, msg: impl Into<String>) -> Diagnostic {
+        Diagnostic::error(code, msg, self.tokens.get_current_token().span())
+    }
+
+    /// 現在のトークンの先頭の位置
+    fn current_pos(&self) -> usize {
+        self.tokens.get_current_token().pos
+    }
+
+    /// `start`から直前に読んだトークンまでの範囲
+    fn span_from(&self, start: usize) -> Span {
+        Span { start, end: self.tokens.prev_end() }
+    }
+
+    /// `start`から直前に読んだトークンまでを範囲とする二項演算のノードを作る
+    fn binary(&self, op: BinOp, lhs: Expr, rhs: Expr, start: usize) -> Expr {
+        let kind = ExprKind::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        Expr::new(kind, self.span_from(start))
+    }
+
+    /// 変数宣言の先頭の型を読む
+    /// - 型でなければ`None`を返す
+    fn base_type(&mut self) -> PResult<Option<Type>> {
+        if self.tokens.consume_type(TypeKind::Int) {
+            Ok(Some(Type::Int))
+        } else if self.tokens.consume_type(TypeKind::Char) {
+            Ok(Some(Type::Char))
+        } else if self.tokens.consume_type(TypeKind::Struct) {
+            Ok(Some(self.struct_decl(false)?))
+        } else if self.tokens.consume_type(TypeKind::Union) {
+            Ok(Some(self.struct_decl(true)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `struct_decl = ident? ("{" (base_type declarator ";")* "}")?`
+    /// - `struct`, `union`は読んだ後に呼ぶ
+    /// - タグだけのときは宣言済みの型を使う (初めてのタグは不完全型として宣言する)
+    /// - 定義の中から自分を参照できるように, メンバを読む前にタグを登録する
+    /// - 定義済みのタグを定義し直すとエラーにする
+    fn struct_decl(&mut self, is_union: bool) -> PResult<Type> {
+        let start = self.current_pos();
+        let tag = self.tokens.consume_ident().map(|t| t.str);
+        let tag_span = self.span_from(start);
+        if !self.tokens.consume("{") {
+            let Some(tag) = tag else {
+                return Err(self.error_here(ErrorCode::ExpectedToken, "expected '{'"));
+            };
+            let agg = self.tags.entry(tag.clone())
+                .or_insert_with(|| Rc::new(Aggregate::new(Some(tag), is_union)));
+            return Ok(Type::Struct(agg.clone()));
+        }
+
+        // 前方宣言された不完全型があればそれを定義する
+        // 定義済みの型は定義し直せないので, 定義の終わりまで読んでから報告する
+        let mut redefinition = None;
+        let agg = match tag.as_ref().and_then(|tag| self.tags.get(tag)) {
+            Some(agg) if !agg.is_complete() => agg.clone(),
+            Some(agg) => {
+                let msg = format!("redefinition of '{}'", agg);
+                redefinition = Some(Diagnostic::error(ErrorCode::Redefinition, msg, tag_span));
+                Rc::new(Aggregate::new(tag.clone(), is_union))
+            }
+            None => {
+                let agg = Rc::new(Aggregate::new(tag.clone(), is_union));
+                if let Some(tag) = tag {
+                    self.tags.insert(tag, agg.clone());
+                }
+                agg
+            }
+        };
+
+        let mut members = Vec::new();
+        let mut incomplete = None;
+        while !self.tokens.consume("}") {
+            let Some(base) = self.base_type()? else {
+                return Err(self.error_here(ErrorCode::ExpectedType, "type name requires a specifier or qualifier"));
+            };
+            let start = self.current_pos();
+            let (name, ty) = self.declarator(base)?;
+            // 大きさが決まらないため, 不完全型はポインタでしかメンバにできない
+            // 定義の終わりまで読んでから報告する
+            if ty.is_incomplete() && incomplete.is_none() {
+                let msg = format!("field has incomplete type '{}'", ty);
+                incomplete = Some(Diagnostic::error(ErrorCode::IncompleteType, msg, self.span_from(start)));
+            }
+            members.push((name, ty));
+            self.tokens.expect(";")?;
+        }
+        if let Some(e) = redefinition.or(incomplete) {
+            return Err(e);
+        }
+        agg.define(members);
+        Ok(Type::Struct(agg))
+    }
+
+    /// `declarator = "*"* ident ("[" num "]")*`
+    /// - 宣言された名前と型を返す
+    fn declarator(&mut self, base: Type) -> PResult<(String, Type)> {
+        let mut ty = base;
+        // 型修飾子を読む
+        while self.tokens.consume("*") {
+            ty = Type::Ptr(Box::new(ty));
+        }
+        let name = if let Some(ident) = self.tokens.consume_ident() {
+            ident.str
+        } else {
+            return Err(self.error_here(ErrorCode::ExpectedIdent, "expected identifier"));
+        };
+        // 配列の要素数を読む
+        // `int a[2][3]`は「intの3要素の配列」の2要素の配列
+        let mut lens = Vec::new();
+        while self.tokens.consume("[") {
+            lens.push(self.tokens.expect_number()? as usize);
+            self.tokens.expect("]")?;
+        }
+        for len in lens.into_iter().rev() {
+            ty = Type::Array(Box::new(ty), len);
+        }
+        Ok((name, ty))
+    }
+
+    /// `params = "(" (base_type declarator ("," base_type declarator)*)? ")"`
+    /// - 引数は宣言した型を付けた変数として返す
+    /// - 配列の引数はポインタになる
+    /// - 構造体の値渡しはできないので, `)`まで読んでからエラーにする
+    fn params(&mut self) -> PResult<Vec<Expr>> {
+        self.tokens.expect("(")?;
         let mut params = Vec::new();
+        let mut aggregate = None;
         // パラメータが無い場合はif文の中身は実行されない
         if !self.tokens.consume(")") {
             // カッコが閉じるまで型と変数を読む
             loop {
-                // 型を読む
-                // 一時的にintのみ読む
-                if !self.tokens.consume_type(TypeKind::Int) {
-                    let e = anyhow!("parameter declaration requires a type");
-                    error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                }
-                let param = match self.tokens.consume_ident() {
-                    Some(t) => Expr::Var(t.str),
-                    None => {
-                        eprintln!("Error While Parsing");
-                        let e = anyhow!("引数は識別子である必要があります");
-                        error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                    }
+                let start = self.current_pos();
+                let Some(base) = self.base_type()? else {
+                    return Err(self.error_here(ErrorCode::ExpectedType, "parameter declaration requires a type"));
                 };
-                // 関数のパラメータは関数スコープで存在しているためローカル変数に追加
-                // 直前で変数以外はエラーになるため, 変数のみ処理
-                match &param {
-                    Expr::Var(str) => self.lvars.push(str.clone()),
-                    _ => unreachable!()
+                let (name, ty) = self.declarator(base)?;
+                if let Type::Struct(_) = ty && aggregate.is_none() {
+                    let msg = format!("passing '{}' by value is not supported", ty);
+                    aggregate = Some(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, self.span_from(start)));
                 }
+                // 関数のパラメータは関数スコープで存在しているためローカル変数に追加
+                self.lvars.push(name.clone());
+                let mut param = Expr::new(ExprKind::Var(name), self.span_from(start));
+                param.ty = Some(ty.decay());
                 params.push(param);
                 if self.tokens.consume(",") {
                     continue;
                 } else {
-                    match self.tokens.expect(")") {
-                        Ok(()) => break,
-                        Err(e) => {
-                            error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                        }
-                    }
+                    self.tokens.expect(")")?;
+                    break;
                 }
             }
         }
-        params
+        match aggregate {
+            Some(e) => Err(e),
+            None => Ok(params),
+        }
     }
-    
+
+    /// `param_types = (param ("," param)* ("," "...")?)? ")"`
+    /// `param = base_type "*"* ident? ("[" num "]")*`
+    /// - プロトタイプ宣言の引数の型と, 可変長引数を取るかを返す
+    /// - 引数の名前は使わないので読み捨てる
+    /// - 配列の引数はポインタになる
+    /// - 構造体の値渡しはできないので, `)`まで読んでからエラーにする
+    /// - `()`は引数を決めない宣言なので`None`を返す
+    fn param_types(&mut self) -> PResult<(Option<Vec<Type>>, bool)> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+        let mut aggregate = None;
+        if self.tokens.consume(")") {
+            return Ok((None, false));
+        }
+        loop {
+            if self.tokens.consume("...") {
+                self.tokens.expect(")")?;
+                variadic = true;
+                break;
+            }
+            let start = self.current_pos();
+            let Some(mut ty) = self.base_type()? else {
+                return Err(self.error_here(ErrorCode::ExpectedType, "parameter declaration requires a type"));
+            };
+            while self.tokens.consume("*") {
+                ty = Type::Ptr(Box::new(ty));
+            }
+            self.tokens.consume_ident();
+            let mut lens = Vec::new();
+            while self.tokens.consume("[") {
+                lens.push(self.tokens.expect_number()? as usize);
+                self.tokens.expect("]")?;
+            }
+            for len in lens.into_iter().rev() {
+                ty = Type::Array(Box::new(ty), len);
+            }
+            if let Type::Struct(_) = ty && aggregate.is_none() {
+                let msg = format!("passing '{}' by value is not supported", ty);
+                aggregate = Some(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, self.span_from(start)));
+            }
+            params.push(ty.decay());
+
+            if !self.tokens.consume(",") {
+                self.tokens.expect(")")?;
+                break;
+            }
+        }
+        match aggregate {
+            Some(e) => Err(e),
+            None => Ok((Some(params), variadic)),
+        }
+    }
+
     /// `args = expr, .. ")"`
-    fn args(&mut self) -> Vec<Expr> {
+    fn args(&mut self) -> PResult<Vec<Expr>> {
         let mut args = Vec::new();
         if !self.tokens.consume(")") {
             loop {
-                let arg = self.expr();
+                let arg = self.expr()?;
                 args.push(arg);
                 if self.tokens.consume(",") {
                     continue;
                 } else {
-                    match self.tokens.expect(")") {
-                        Ok(()) => break,
-                        Err(e) => {
-                            error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                        }
-                    }
+                    self.tokens.expect(")")?;
+                    break;
                 }
             }
         }
-        args
+        Ok(args)
     }
-    
+
     /// defun = ident "(" params ")" "{" stmt* "}"
-    pub fn defun(&mut self) -> Stmt {
+    /// - 関数の中で見つかった全てのエラーを返す
+    pub fn defun(&mut self) -> Result<Stmt, Vec<Diagnostic>> {
+        self.errors.clear();
+        match self.defun_inner() {
+            Ok(node) if self.errors.is_empty() => Ok(node),
+            Ok(_) => Err(std::mem::take(&mut self.errors)),
+            Err(e) => {
+                // 関数の宣言部分で失敗したら次の関数定義まで読み飛ばす
+                self.errors.push(e);
+                self.synchronize_toplevel();
+                Err(std::mem::take(&mut self.errors))
+            }
+        }
+    }
+
+    fn defun_inner(&mut self) -> PResult<Stmt> {
         // ローカル変数の配列を初期化
         self.lvars.clear();
+        let start = self.current_pos();
 
         // 関数の戻り値の型を読む
         // 一時的にintのみ読む
         if !self.tokens.consume_type(TypeKind::Int) {
-            let e = anyhow!("type specifier missing");
-            error_at(self.tokens.input, self.tokens.get_current_token().pos, e);
+            return Err(self.error_here(ErrorCode::ExpectedType, "type specifier missing"));
         }
 
         // 関数名を読む
         let fn_name: String = match self.tokens.consume_ident() {
             Some(ident) => ident.str,
             None => {
-                eprintln!("Error While Parsing");
-                let e = anyhow!("関数名が見つかりません");
-                error_at(self.tokens.input, self.tokens.get_current_token().pos, e);
+                return Err(self.error_here(ErrorCode::ExpectedIdent, "関数名が見つかりません"));
             }
         };
 
         // 関数名の重複を調べる
-        if self.defined_fn.contains(&fn_name) {
-            eprintln!("Error While Parsing");
-            let e = anyhow!("関数が重複して定義されています");
-            error_at(self.tokens.input, self.tokens.get_current_token().pos, e);
+        // プロトタイプ宣言だけされている関数は定義してよい
+        if self.defined_fn.contains(&fn_name) || self.globals.contains(&fn_name) || self.extern_globals.contains(&fn_name) {
+            return Err(self.error_here(ErrorCode::Redefinition, "関数が重複して定義されています"));
         } else {
             self.defined_fn.push(fn_name.clone());
         }
+        if !self.declared_fn.contains(&fn_name) {
+            self.declared_fn.push(fn_name.clone());
+        }
 
-        let params = self.params();
+        let params = self.params()?;
 
-        self.tokens.expect("{").unwrap_or_else( |e|{
-            eprintln!("Error While Parsing");
-            error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-        });
-        
-        let mut body = Vec::new();
-        while !self.tokens.consume("}") {
-            body.push(self.stmt());
-        }
-        Stmt::Fn { fn_name, params, body }
+        self.tokens.expect("{")?;
+
+        let body = self.block_body();
+        Ok(Stmt::new(StmtKind::Fn { fn_name, params, body }, self.span_from(start)))
     }
-    
-    /// stmt = "int" ident ";" | 
+
+    /// stmt = base_type declarator? ";" |
     ///        "while" "(" expr ")" stmt |
     ///        "if"  "(" expr ")" stmt ("else" stmt)? |
     ///        "for" "(" expr? ";" expr? ";" expr? ")" stmt |
     ///        "{" stmt* "}" |
     ///        "return" expr ";" |
     ///        expr ";" |
-    fn stmt(&mut self) -> Stmt {
-        if self.tokens.consume_type(TypeKind::Int) {
-            // 変数宣言
-            let mut ty = Type::Int;
-            // 型修飾子を読む
-            while self.tokens.consume("*") {
-                ty = Type::Ptr(Box::new(ty));
-            }
-            let name = if let Some(ident) = self.tokens.consume_ident() {
-                ident.str
-            } else {
-                let e = anyhow!("expected identifier");
-                error_at(self.tokens.input, self.tokens.get_current_token().pos, e)
-            };
-            if let Err(e) = self.tokens.expect(";") {
-                error_at(self.tokens.input, self.tokens.get_current_token().pos, e)
+    fn stmt(&mut self) -> PResult<Stmt> {
+        let start = self.current_pos();
+        let kind = self.stmt_kind()?;
+        Ok(Stmt::new(kind, self.span_from(start)))
+    }
+
+    fn stmt_kind(&mut self) -> PResult<StmtKind> {
+        if let Some(base) = self.base_type()? {
+            // 構造体のタグの定義だけなら何もしない
+            if self.tokens.consume(";") {
+                return Ok(StmtKind::Block(Vec::new()));
             }
+            // 変数宣言
+            let (name, ty) = self.declarator(base)?;
+            self.tokens.expect(";")?;
             self.lvars.push(name.clone());
-            Stmt::VarDecl { name, ty }
+            Ok(StmtKind::VarDecl { name, ty })
         } else if self.tokens.consume_keyword(TK_WHILE) {
             // while文
-            self.tokens.expect("(").unwrap_or_else( |e|{
-                eprintln!("Error While Parsing");
-                error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-            });
-
-            let cond = self.expr();
+            self.tokens.expect("(")?;
+            let cond = self.expr()?;
+            self.tokens.expect(")")?;
 
-            self.tokens.expect(")").unwrap_or_else( |e|{
-                eprintln!("Error While Parsing");
-                error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-            });
+            let body = self.stmt()?;
 
-            let body = self.stmt();
-            
-            Stmt::While { cond, body: Box::new(body) }
+            Ok(StmtKind::While { cond, body: Box::new(body) })
         } else if self.tokens.consume_keyword(TK_FOR) {
             // for文をパース
-            self.tokens.expect("(").unwrap_or_else( |e|{
-                eprintln!("Error While Parsing");
-                error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-            });
-            
+            self.tokens.expect("(")?;
+
             let init = match self.tokens.consume(";") {
                 true => {
                     None
                 }
                 false => {
-                    let _init = self.expr();
-                    self.tokens.expect(";").unwrap_or_else( |e|{
-                        eprintln!("Error While Parsing");
-                        error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                    });
+                    let _init = self.expr()?;
+                    self.tokens.expect(";")?;
                     Some(Box::new(_init))
                 }
             };
@@ -214,11 +625,8 @@ impl<'a> Parser<'a> {
                     None
                 }
                 false => {
-                    let _cond = self.expr();
-                    self.tokens.expect(";").unwrap_or_else( |e|{
-                        eprintln!("Error While Parsing");
-                        error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                    });
+                    let _cond = self.expr()?;
+                    self.tokens.expect(";")?;
                     Some(Box::new(_cond))
                 }
             };
@@ -227,306 +635,284 @@ impl<'a> Parser<'a> {
                     None
                 }
                 false => {
-                    let _update = self.expr();
-                    self.tokens.expect(")").unwrap_or_else( |e|{
-                        eprintln!("Error While Parsing");
-                        error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                    });
+                    let _update = self.expr()?;
+                    self.tokens.expect(")")?;
                     Some(Box::new(_update))
                 }
             };
-            let body = self.stmt();
-            Stmt::For { init, cond, update, body: Box::new(body) }
+            let body = self.stmt()?;
+            Ok(StmtKind::For { init, cond, update, body: Box::new(body) })
         } else if self.tokens.consume_keyword(TK_IF) {
             // if文をパース
             // 条件のパース
-            self.tokens.expect("(").unwrap_or_else( |e|{
-                eprintln!("Error While Parsing");
-                error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-            });
-
-            let cond = self.expr();
-            
-            self.tokens.expect(")").unwrap_or_else( |e|{
-                eprintln!("Error While Parsing");
-                error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-            });
-            
+            self.tokens.expect("(")?;
+            let cond = self.expr()?;
+            self.tokens.expect(")")?;
+
             // thenのパース
-            let then = self.stmt();
-            
+            let then = self.stmt()?;
+
             // elseの有無で分岐
             let els = if self.tokens.consume_keyword(TK_ELSE) {
-                Some(Box::new(self.stmt()))
+                Some(Box::new(self.stmt()?))
             } else {
                 None
             };
-            Stmt::If { 
-                cond, 
-                then: Box::new(then), 
+            Ok(StmtKind::If {
+                cond,
+                then: Box::new(then),
                 els,
-            }
+            })
         } else if self.tokens.consume("{") {
             // ブロックをパース
-            let mut block_stmt = vec![];
-            while !self.tokens.consume("}") {
-                block_stmt.push(self.stmt());
-            }
-            Stmt::Block(block_stmt)
+            let block_stmt = self.block_body();
+            Ok(StmtKind::Block(block_stmt))
         } else {
-            let node;
-            
-            if self.tokens.consume_keyword(TK_RETURN) {
+            let node = if self.tokens.consume_keyword(TK_RETURN) {
                 // return文の場合
                 // 木は左から埋めていく
-                node = Stmt::Return(self.expr());
-            } else { 
+                StmtKind::Return(self.expr()?)
+            } else {
                 // それ以外は式 (expr)
-                node = Stmt::ExprStmt(self.expr());
-            }
+                StmtKind::ExprStmt(self.expr()?)
+            };
 
             // セミコロンで文が閉じているか
-            match self.tokens.expect(";") {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error While Parsing");
-                    error_at(self.tokens.input, self.tokens.get_current_token().pos, e);
-                }
-            }
-            node
+            self.tokens.expect(";")?;
+            Ok(node)
         }
     }
-    
+
     /// `expr = assign`
-    fn expr(&mut self) -> Expr {
+    fn expr(&mut self) -> PResult<Expr> {
         self.assign()
     }
-    
+
     /// `assign = equiality ("=" equiality)?`
-    fn assign(&mut self) -> Expr {
-        let pos = self.tokens.get_current_token().pos;
-        let node = self.equiality();
-        
+    fn assign(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let node = self.equiality()?;
+
         if self.tokens.consume("=") {
-            let rhs = self.equiality();
-            match node {
-                Expr::Var(_) => (),
-                Expr::Deref(_) => (),
+            let rhs = self.equiality()?;
+            match node.kind {
+                ExprKind::Var(_) => (),
+                ExprKind::Deref(_) => (),
+                ExprKind::Member { .. } => (),
                 _ => {
-                    let e = anyhow!("left value is not assignable");
-                    error_at(self.tokens.input, pos, e)
+                    return Err(Diagnostic::error(ErrorCode::NotAssignable, "left value is not assignable", node.span));
                 }
             }
-            Expr::Assign { 
-                lhs: Box::new(node), 
-                rhs: Box::new(rhs) 
-            }
+            let kind = ExprKind::Assign {
+                lhs: Box::new(node),
+                rhs: Box::new(rhs)
+            };
+            Ok(Expr::new(kind, self.span_from(start)))
         } else {
-            return node;
+            Ok(node)
         }
     }
 
     /// `equiality = relational ( "==" relational | "!=" relational )*`
-    fn equiality(&mut self) -> Expr {
-        let mut node = self.relational();
-        
+    fn equiality(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let mut node = self.relational()?;
+
         loop {
             if self.tokens.consume("==") {
-                node = Expr::Binary { 
-                    op: BinOp::Eq, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.relational()) 
-                };
+                let rhs = self.relational()?;
+                node = self.binary(BinOp::Eq, node, rhs, start);
             } else if self.tokens.consume("!=") {
-                node = Expr::Binary { 
-                    op: BinOp::Ne, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.relational()) 
-                };
+                let rhs = self.relational()?;
+                node = self.binary(BinOp::Ne, node, rhs, start);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    
+
     /// `relational = add ( "<" add | "<=" add | ">" add | ">=" add )*`
-    fn relational(&mut self) -> Expr {
-        let mut node = self.add();
-        
+    fn relational(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let mut node = self.add()?;
+
         // 長いトークンから見ていく
         loop {
             if self.tokens.consume("<=") {
-                node = Expr::Binary { 
-                    op: BinOp::Le, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.add()) 
-                };
+                let rhs = self.add()?;
+                node = self.binary(BinOp::Le, node, rhs, start);
             } else if self.tokens.consume("<") {
-                node = Expr::Binary { 
-                    op: BinOp::Lt, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.add()) 
-                };
+                let rhs = self.add()?;
+                node = self.binary(BinOp::Lt, node, rhs, start);
             } else if self.tokens.consume(">=") {
                 // 逆にするだけ
-                node = Expr::Binary { 
-                    op: BinOp::Le, 
-                    lhs: Box::new(self.add()),
-                    rhs: Box::new(node), 
-                };
+                let lhs = self.add()?;
+                node = self.binary(BinOp::Le, lhs, node, start);
             } else if self.tokens.consume(">") {
                 // 逆にするだけ
-                node = Expr::Binary { 
-                    op: BinOp::Lt, 
-                    lhs: Box::new(self.add()),
-                    rhs: Box::new(node), 
-                };
+                let lhs = self.add()?;
+                node = self.binary(BinOp::Lt, lhs, node, start);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
 
     /// `add = mul ( "+" mul | "-" mul )*`
-    fn add(&mut self) -> Expr {
-        let mut node = self.mul();
+    fn add(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let mut node = self.mul()?;
 
         loop {
             if self.tokens.consume("+") {
-                node = Expr::Binary { 
-                    op: BinOp::Add, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.mul()) 
-                };
+                let rhs = self.mul()?;
+                node = self.binary(BinOp::Add, node, rhs, start);
             } else if self.tokens.consume("-") {
-                node = Expr::Binary { 
-                    op: BinOp::Sub, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.mul()) 
-                };
+                let rhs = self.mul()?;
+                node = self.binary(BinOp::Sub, node, rhs, start);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
 
     /// `mul = unary ( "*" unary | "/" unary )*`
-    fn mul(&mut self) -> Expr {
-        let mut node = self.unary();
+    fn mul(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let mut node = self.unary()?;
 
         loop {
             if self.tokens.consume("*") {
-                node = Expr::Binary { 
-                    op: BinOp::Mul, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.unary()) 
-                };
+                let rhs = self.unary()?;
+                node = self.binary(BinOp::Mul, node, rhs, start);
             } else if self.tokens.consume("/") {
-                node = Expr::Binary { 
-                    op: BinOp::Div, 
-                    lhs: Box::new(node), 
-                    rhs: Box::new(self.unary()) 
-                };
+                let rhs = self.unary()?;
+                node = self.binary(BinOp::Div, node, rhs, start);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    
-    /// unary = "+" primary | 
-    ///         "-" primary |
+
+    /// unary = "+" postfix |
+    ///         "-" postfix |
     ///         "&" unary |
-    ///         "*" unary
-    fn unary(&mut self) -> Expr {
+    ///         "*" unary |
+    ///         postfix
+    fn unary(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
         if self.tokens.consume("+") {
-            self.primary()
+            self.postfix()
         } else if self.tokens.consume("-") {
             // 一時的に 0-primary() の形で負の数を表す
-            Expr::Binary { 
-                op: BinOp::Sub,
-                lhs: Box::new(Expr::Num(0)),
-                rhs: Box::new(self.primary()),
-            }
+            // 0は`-`の位置にあるものとする
+            let zero = Expr::new(ExprKind::Num(0), self.span_from(start));
+            let rhs = self.postfix()?;
+            Ok(self.binary(BinOp::Sub, zero, rhs, start))
         } else if self.tokens.consume("&") {
-            let pos = self.tokens.get_current_token().pos;
-            let var = self.unary();
-            match var {
-                Expr::Var(_) => Expr::Addr(Box::new(var)),
-                _ => {
-                    let e = anyhow!("this cannot be refecenced");
-                    error_at(self.tokens.input, pos, e)
-                }
+            let var = self.unary()?;
+            match var.kind {
+                ExprKind::Var(_) | ExprKind::Deref(_) | ExprKind::Member { .. } => Ok(Expr::new(ExprKind::Addr(Box::new(var)), self.span_from(start))),
+                _ => Err(Diagnostic::error(ErrorCode::NotAddressable, "this cannot be refecenced", var.span)),
             }
         } else if self.tokens.consume("*") {
-            let pos = self.tokens.get_current_token().pos;
-            let addr = self.unary();
-            // 参照外し可能か検証
-            match addr {
-                Expr::Deref(_) | Expr::Var(_) => Expr::Deref(Box::new(addr)),
-                _ => {
-                    let e = anyhow!("the value cannot be dereferenced");
-                    error_at(self.tokens.input, pos, e)
-                }
-            }
+            // 参照外しできるかは型検査で調べる
+            let addr = self.unary()?;
+            Ok(Expr::new(ExprKind::Deref(Box::new(addr)), self.span_from(start)))
         } else {
-            self.primary()
+            self.postfix()
+        }
+    }
+
+    /// postfix = primary ( "[" expr "]" | "." ident | "->" ident )*
+    /// - `a[i]`は`*(a + i)`として読む
+    /// - `p->x`は`(*p).x`として読む
+    fn postfix(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+        let mut node = self.primary()?;
+
+        loop {
+            if self.tokens.consume("[") {
+                let index = self.expr()?;
+                self.tokens.expect("]")?;
+                let addr = self.binary(BinOp::Add, node, index, start);
+                node = Expr::new(ExprKind::Deref(Box::new(addr)), self.span_from(start));
+            } else if self.tokens.consume(".") {
+                let name = self.member_name()?;
+                node = Expr::new(ExprKind::Member { base: Box::new(node), name }, self.span_from(start));
+            } else if self.tokens.consume("->") {
+                let base = Expr::new(ExprKind::Deref(Box::new(node)), self.span_from(start));
+                let name = self.member_name()?;
+                node = Expr::new(ExprKind::Member { base: Box::new(base), name }, self.span_from(start));
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    /// `.`, `->`の後のメンバ名を読む
+    fn member_name(&mut self) -> PResult<String> {
+        match self.tokens.consume_ident() {
+            Some(ident) => Ok(ident.str),
+            None => Err(self.error_here(ErrorCode::ExpectedIdent, "expected member name")),
         }
     }
 
     /// primary = num |
+    ///           str+ |
     ///           ident ( "(" params ")" )? |
-    ///           "(" expr ")" 
-    fn primary(&mut self) -> Expr {
+    ///           "(" expr ")"
+    fn primary(&mut self) -> PResult<Expr> {
+        let start = self.current_pos();
+
+        // 隣り合う文字列リテラルは連結する
+        if let Some(mut bytes) = self.tokens.consume_str() {
+            while let Some(next) = self.tokens.consume_str() {
+                bytes.extend(next);
+            }
+            return Ok(Expr::new(ExprKind::Str(bytes), self.span_from(start)));
+        }
+
         // "(" expr ")"
+        // 範囲は括弧を含める
         if self.tokens.consume("(") {
-            let expr = self.expr();
-            match self.tokens.expect(")") {
-                Ok(()) => (),
-                Err(e) => {
-                    eprintln!("Error While Parsing");
-                    error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
-                }
-            };
-            return expr;
+            let expr = self.expr()?;
+            self.tokens.expect(")")?;
+            return Ok(Expr::new(expr.kind, self.span_from(start)));
         }
 
         // ident ( args )?
-        let pos_ident = self.tokens.get_current_token().pos;
         if let Some(ident) = self.tokens.consume_ident() {
             // 関数かどうか調べる
-            let args;
             if self.tokens.consume("(") {
-                // 定義済みか調べる
-                if !self.defined_fn.contains(&ident.str) {
-                    let e = anyhow!("定義されていない関数を呼び出しています");
-                    error_at(self.tokens.input, pos_ident, e);
+                // 宣言済みか調べる
+                // 定義は他のファイルにあってもよい (リンク時に解決する)
+                if !self.declared_fn.contains(&ident.str) {
+                    return Err(Diagnostic::error(ErrorCode::UndefinedFn, "宣言されていない関数を呼び出しています", ident.span()));
                 }
-                args = self.args();
-                return Expr::Call { fn_name: ident.str, args };
+                let args = self.args()?;
+                return Ok(Expr::new(ExprKind::Call { fn_name: ident.str, args }, self.span_from(start)));
             }
             else {
                 // 定義済みか調べる
-                if !self.lvars.contains(&ident.str) {
-                    let e = anyhow!("Use of undeclared identifier '{}'", ident.str);
-                    error_at(self.tokens.input, pos_ident, e);
+                if !self.lvars.contains(&ident.str) && !self.globals.contains(&ident.str) && !self.extern_globals.contains(&ident.str) {
+                    let msg = format!("Use of undeclared identifier '{}'", ident.str);
+                    return Err(Diagnostic::error(ErrorCode::UndeclaredVar, msg, ident.span()));
                 }
-                return Expr::Var(ident.str);
-            } 
+                return Ok(Expr::new(ExprKind::Var(ident.str), self.span_from(start)));
+            }
         }
 
-        let num = match self.tokens.expect_number() {
-            Ok(val) => val,
+        match self.tokens.expect_number() {
+            Ok(val) => Ok(Expr::new(ExprKind::Num(val), self.span_from(start))),
             Err(e) => {
-                eprintln!("Error While Parsing");
-                let e_unmatch = anyhow!("Error: unmatched `}}`");
                 if "}" == self.tokens.get_current_token().str {
-                    error_at(&self.tokens.input, self.tokens.get_current_token().pos, e_unmatch);
+                    Err(self.error_here(ErrorCode::UnmatchedBrace, "unmatched `}`"))
                 } else {
-                    error_at(&self.tokens.input, self.tokens.get_current_token().pos, e);
+                    Err(e)
                 }
             }
-        };
-        Expr::Num(num)
+        }
     }
-    
-}
\ No newline at end of file
+
+}
@@ -1,2 +1,3 @@
 pub mod interval_analysis;
+pub mod liveness;
 pub mod register_allocation;
\ No newline at end of file
@@ -1,4 +1,6 @@
 use crate::ir::types_ir::{VirtualReg, ThreeAddressCode as TAC};
+use crate::reg_alloc::liveness;
+use crate::reg_alloc::register_allocation::Location;
 use std::collections::HashMap;
 
 #[derive(Debug, PartialEq)]
@@ -6,45 +8,45 @@ pub struct Interval {
     pub vreg: VirtualReg,
     pub start: usize,
     pub end: usize,
-    pub reg: Option<usize>,
+    pub reg: Option<Location>,
 }
 
 impl Interval {
     fn new(virtual_reg: VirtualReg, start: usize, end: usize) -> Interval {
-        Interval { vreg: virtual_reg, start: start, end, reg: None }
+        Interval { vreg: virtual_reg, start, end, reg: None }
     }
 }
 
 /// 生存区間を記録する
-/// - 先頭から見て初めて出現した位置がstart
-/// - 後方から見て初めて出現した位置がend
-pub fn scan_interval(code: &Vec<TAC>) -> Vec<Interval> {
+/// - 生存解析の結果から, 定義または生存している最初の位置がstart
+/// - 同様に最後の位置がend
+/// - ループをまたいで生存する値は後方への分岐まで区間が伸びる
+pub fn scan_interval(code: &[TAC]) -> Vec<Interval> {
+    let liveness = liveness::analyze(code);
     let mut start = HashMap::new();
     let mut end = HashMap::new();
 
-    // 先頭から見てレジスタ名と位置を記録
     for (i, tac) in code.iter().enumerate() {
-        // 命令から使用しているレジスタを取得
-        for reg in tac.get_using_regs() {
+        let regs = tac.get_def_regs().into_iter()
+            .chain(liveness.live_in[i].iter().copied())
+            .chain(liveness.live_out[i].iter().copied());
+        for reg in regs {
             start.entry(reg).or_insert(i);
-        }
-    }
-
-    for (i, tac) in code.iter().enumerate().rev() {
-        // 命令から使用しているレジスタを取得
-        for reg in tac.get_using_regs() {
-            end.entry(reg).or_insert(i);
+            end.insert(reg, i);
         }
     }
     
     // 両方のHashMapを使ってインターバルを集める
     let mut intervals: Vec<Interval> = vec![];
     for (vreg, start_idx) in start {
-        let end_idx = end.get(&vreg).unwrap().clone();
+        let end_idx = *end.get(&vreg).unwrap();
         let interval = Interval::new(vreg, start_idx, end_idx);
         intervals.push(interval);
     }
 
+    // 割り当て結果が実行ごとに変わらないように並べる
+    intervals.sort_by_key(|i| (i.start, i.vreg.id));
+
     intervals
 }
 
@@ -74,6 +76,7 @@ fn test_scan_adding_interval() {
                          left: VirtualReg { id: 0 }, 
                          op: crate::ir::types_ir::BinOp::Add, 
                          right: VirtualReg { id: 1 },
+                         width: crate::ir::types_ir::Width::Dword,
                        }
     ];
     
@@ -87,4 +90,78 @@ fn test_scan_adding_interval() {
     ];
     
     assert_eq!(intervals, expected);
-}
\ No newline at end of file
+}
+
+#[test]
+/// ループの前で定義してループの先頭で使う値
+/// - 後方への分岐 (GoTo) まで生存区間が伸びる
+fn test_scan_loop_interval() {
+    use crate::ir::types_ir::{BinOp, Label, Width};
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 10 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 0 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 1 }, op: BinOp::Lt, right: VirtualReg { id: 0 }, width: Width::Dword },
+        TAC::IfFalse { cond: VirtualReg { id: 2 }, label: Label::Lend(1) },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 1 },
+        TAC::BinOpCode { dest: VirtualReg { id: 1 }, left: VirtualReg { id: 1 }, op: BinOp::Add, right: VirtualReg { id: 3 }, width: Width::Dword },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ];
+
+    let intervals = scan_interval(&ir);
+
+    let expected = vec![
+        Interval { vreg: VirtualReg { id: 0 }, start: 0, end: 7, reg: None },
+        Interval { vreg: VirtualReg { id: 1 }, start: 1, end: 9, reg: None },
+        Interval { vreg: VirtualReg { id: 2 }, start: 3, end: 4, reg: None },
+        Interval { vreg: VirtualReg { id: 3 }, start: 5, end: 6, reg: None },
+    ];
+    assert_eq!(intervals, expected);
+}
+
+#[test]
+/// 二重ループ
+/// - 外側のループの先頭で使う値は外側の後方分岐まで伸びる
+/// - 内側のループの先頭だけで使う値は内側の後方分岐で終わる
+fn test_scan_nested_loop_interval() {
+    use crate::ir::types_ir::{BinOp, Label, Width};
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 3 },
+        TAC::LoadImm { dest: VirtualReg { id: 1 }, value: 0 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 1 }, op: BinOp::Lt, right: VirtualReg { id: 0 }, width: Width::Dword },
+        TAC::IfFalse { cond: VirtualReg { id: 2 }, label: Label::Lend(1) },
+        TAC::LoadImm { dest: VirtualReg { id: 3 }, value: 2 },
+        TAC::LoadImm { dest: VirtualReg { id: 4 }, value: 0 },
+        TAC::Label { label: Label::Lbegin(2) },
+        TAC::BinOpCode { dest: VirtualReg { id: 5 }, left: VirtualReg { id: 4 }, op: BinOp::Lt, right: VirtualReg { id: 3 }, width: Width::Dword },
+        TAC::IfFalse { cond: VirtualReg { id: 5 }, label: Label::Lend(3) },
+        TAC::LoadImm { dest: VirtualReg { id: 6 }, value: 1 },
+        TAC::BinOpCode { dest: VirtualReg { id: 4 }, left: VirtualReg { id: 4 }, op: BinOp::Add, right: VirtualReg { id: 6 }, width: Width::Dword },
+        TAC::GoTo { label: Label::Lbegin(2) },
+        TAC::Label { label: Label::Lend(3) },
+        TAC::LoadImm { dest: VirtualReg { id: 7 }, value: 1 },
+        TAC::BinOpCode { dest: VirtualReg { id: 1 }, left: VirtualReg { id: 1 }, op: BinOp::Add, right: VirtualReg { id: 7 }, width: Width::Dword },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+        TAC::Return { src: VirtualReg { id: 1 } },
+    ];
+
+    let intervals = scan_interval(&ir);
+
+    let expected = vec![
+        Interval { vreg: VirtualReg { id: 0 }, start: 0, end: 16, reg: None },
+        Interval { vreg: VirtualReg { id: 1 }, start: 1, end: 18, reg: None },
+        Interval { vreg: VirtualReg { id: 2 }, start: 3, end: 4, reg: None },
+        Interval { vreg: VirtualReg { id: 3 }, start: 5, end: 12, reg: None },
+        Interval { vreg: VirtualReg { id: 4 }, start: 6, end: 12, reg: None },
+        Interval { vreg: VirtualReg { id: 5 }, start: 8, end: 9, reg: None },
+        Interval { vreg: VirtualReg { id: 6 }, start: 10, end: 11, reg: None },
+        Interval { vreg: VirtualReg { id: 7 }, start: 14, end: 15, reg: None },
+    ];
+    assert_eq!(intervals, expected);
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use crate::ir::cfg::Cfg;
+use crate::ir::types_ir::{ThreeAddressCode as TAC, VirtualReg};
+
+/// 各命令の入口と出口で生存している仮想レジスタ
+#[derive(Debug)]
+pub struct Liveness {
+    pub live_in: Vec<HashSet<VirtualReg>>,
+    pub live_out: Vec<HashSet<VirtualReg>>,
+}
+
+/// 後ろ向きのデータフロー解析で生存情報を求める
+/// - ブロック単位で live_out[b] = ∪ live_in[s] (sはbの後続) を求める
+/// - ブロックの中は後ろから live_in[i] = use[i] ∪ (live_out[i] - def[i])
+/// - ループの後方分岐があるため変化が無くなるまで繰り返す
+pub fn analyze(code: &[TAC]) -> Liveness {
+    let cfg = Cfg::new(code);
+    let len = cfg.blocks.len();
+
+    let mut block_in: Vec<HashSet<VirtualReg>> = vec![HashSet::new(); len];
+    let mut block_out: Vec<HashSet<VirtualReg>> = vec![HashSet::new(); len];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // 後ろから見ると早く収束する
+        for b in (0..len).rev() {
+            let mut out = HashSet::new();
+            for s in &cfg.blocks[b].succs {
+                out.extend(block_in[*s].iter().copied());
+            }
+            let inn = transfer(&cfg.blocks[b].code, &out);
+
+            if inn != block_in[b] || out != block_out[b] {
+                changed = true;
+                block_in[b] = inn;
+                block_out[b] = out;
+            }
+        }
+    }
+
+    // ブロックの出口から命令ごとの生存情報を求める
+    // ブロックは元の命令列と同じ順に並んでいる
+    let mut live_in = Vec::new();
+    let mut live_out = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut live = block_out[b].clone();
+        let mut ins = Vec::new();
+        let mut outs = Vec::new();
+        for tac in block.code.iter().rev() {
+            outs.push(live.clone());
+            live = step(tac, &live);
+            ins.push(live.clone());
+        }
+        live_in.extend(ins.into_iter().rev());
+        live_out.extend(outs.into_iter().rev());
+    }
+
+    Liveness { live_in, live_out }
+}
+
+/// 命令ひとつ分だけ生存情報を遡る
+fn step(tac: &TAC, live_out: &HashSet<VirtualReg>) -> HashSet<VirtualReg> {
+    let defs = tac.get_def_regs();
+    let mut live: HashSet<VirtualReg> = live_out.iter()
+        .filter(|r| !defs.contains(r))
+        .copied()
+        .collect();
+    live.extend(tac.get_use_regs());
+    live
+}
+
+/// ブロックの出口の生存情報から入口の生存情報を求める
+fn transfer(code: &[TAC], live_out: &HashSet<VirtualReg>) -> HashSet<VirtualReg> {
+    let mut live = live_out.clone();
+    for tac in code.iter().rev() {
+        live = step(tac, &live);
+    }
+    live
+}
+
+#[test]
+/// ループの先頭で使う値はループの末尾の分岐まで生存する
+fn test_live_across_back_edge() {
+    use crate::ir::types_ir::{BinOp, Label, Width};
+
+    let ir = vec![
+        TAC::LoadImm { dest: VirtualReg { id: 0 }, value: 10 },
+        TAC::Label { label: Label::Lbegin(0) },
+        TAC::BinOpCode { dest: VirtualReg { id: 1 }, left: VirtualReg { id: 0 }, op: BinOp::Lt, right: VirtualReg { id: 0 }, width: Width::Dword },
+        TAC::IfFalse { cond: VirtualReg { id: 1 }, label: Label::Lend(1) },
+        TAC::GoTo { label: Label::Lbegin(0) },
+        TAC::Label { label: Label::Lend(1) },
+    ];
+
+    let liveness = analyze(&ir);
+    assert!(liveness.live_out[4].contains(&VirtualReg { id: 0 }));
+    assert!(!liveness.live_in[5].contains(&VirtualReg { id: 0 }));
+    assert!(!liveness.live_in[0].contains(&VirtualReg { id: 0 }));
+}
@@ -1,11 +1,25 @@
 use std::collections::{HashMap, HashSet};
+use crate::frame::Frame;
 use crate::ir::types_ir::VirtualReg;
 use crate::reg_alloc::interval_analysis::Interval;
 
+/// 仮想レジスタの割り当て先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Reg(usize),   // 物理レジスタの番号
+    Spill(usize), // スタック上のスロット [rbp - offset]
+}
+
 /// 線形スキャンレジスタ割り当て
 /// - 開始時刻でソート
 /// - その時点でアクティブなレジスタを記録しておく
-pub fn linear_reg_alloc(intervals: &mut Vec<Interval>, reg_count: usize) -> HashMap<VirtualReg, usize> {
+/// - レジスタが足りないときは終了が最も遅いものをスピルする
+/// - 同じ位置から始まる区間同士はレジスタを共有しない
+/// - 変数はフレーム上にあるので割り当てない
+pub fn linear_reg_alloc(intervals: &mut Vec<Interval>, reg_count: usize, frame: &mut Frame) -> HashMap<VirtualReg, Location> {
+    // 変数は常にスタックから読み書きするためレジスタは不要
+    intervals.retain(|i| !frame.is_var(&i.vreg));
+
     // 開始時間でソート
     intervals.sort_by_key(|i| i.start);
     
@@ -13,26 +27,45 @@ pub fn linear_reg_alloc(intervals: &mut Vec<Interval>, reg_count: usize) -> Hash
     
     for interval in &mut *intervals {
         // 生存しているものを残す
-        active.retain(|a| a.end > interval.start);
+        // 同じ位置から始まる区間は同じ命令で定義される値で, 命令の中で順に書き込まれる
+        // (今は関数の引数だけ)
+        // そのため使われずにすぐ終わる区間でも, 同じ位置から始まる区間とはレジスタを共有させない
+        active.retain(|a| a.end > interval.start || a.start == interval.start);
         
         if active.len() < reg_count {
             // レジスタに空きがある場合
             // ここでpanicするならバグ
             let reg_idx = find_free_register(&active, reg_count).unwrap();
             
-            interval.reg = Some(reg_idx);
+            interval.reg = Some(Location::Reg(reg_idx));
+            active.push(interval);
         } else {
-            unimplemented!("no handle for spill");
+            // 終了が最も遅いものを選ぶ
+            let (idx, spill) = active.iter()
+                .enumerate()
+                .max_by_key(|(_, a)| a.end)
+                .map(|(idx, a)| (idx, a.end))
+                .unwrap();
+
+            if spill > interval.end {
+                // アクティブな区間のレジスタを奪う
+                let victim = active.remove(idx);
+                interval.reg = victim.reg;
+                victim.reg = Some(Location::Spill(frame.alloc_spill_slot()));
+                active.push(interval);
+            } else {
+                // 自分自身をスピルする
+                interval.reg = Some(Location::Spill(frame.alloc_spill_slot()));
+            }
         }
-        active.push(interval);
     }
     
-    let mut vreg_to_reg = HashMap::new();
+    let mut vreg_to_loc = HashMap::new();
     for interval in intervals {
-        vreg_to_reg.insert(interval.vreg, interval.reg.unwrap());
+        vreg_to_loc.insert(interval.vreg, interval.reg.unwrap());
     }
     
-    vreg_to_reg
+    vreg_to_loc
 }
 
 /// active配列を受け取って空いているレジスタを調べる
@@ -41,16 +74,11 @@ pub fn linear_reg_alloc(intervals: &mut Vec<Interval>, reg_count: usize) -> Hash
 fn find_free_register(active: &Vec<&mut Interval>, reg_count: usize) -> Option<usize> {
     let mut used = HashSet::new();
     for interval in active {
-        if let Some(reg) = interval.reg {
+        if let Some(Location::Reg(reg)) = interval.reg {
             used.insert(reg);
         }
     }
-    for i in 0..reg_count {
-        if !used.contains(&i) {
-            return Some(i);
-        }
-    }
-    None
+    (0..reg_count).find(|i| !used.contains(i))
 }
 
 #[test]
@@ -62,13 +90,14 @@ fn test_alloc_binop() {
         Interval { vreg: VirtualReg { id: 2 }, start: 2, end: 2, reg: None }
     ];
 
-    let mut result: Vec<(VirtualReg, usize)> = linear_reg_alloc(&mut intervals, 8).into_iter().collect();
-    result.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+    let mut frame = Frame::from_lvar_map(HashMap::new());
+    let mut result: Vec<(VirtualReg, Location)> = linear_reg_alloc(&mut intervals, 8, &mut frame).into_iter().collect();
+    result.sort_by_key(|a| a.0.id);
     
     let expected = vec![
-        (VirtualReg { id: 0 }, 0),
-        (VirtualReg { id: 1 }, 1),
-        (VirtualReg { id: 2 }, 0),
+        (VirtualReg { id: 0 }, Location::Reg(0)),
+        (VirtualReg { id: 1 }, Location::Reg(1)),
+        (VirtualReg { id: 2 }, Location::Reg(0)),
     ];
     
     assert_eq!(result, expected);
@@ -85,15 +114,16 @@ fn test_alloc_longer_op1() {
         Interval { vreg: VirtualReg { id: 4 }, start: 4, end: 4, reg: None }, 
     ];
     
-    let mut result: Vec<(VirtualReg, usize)> = linear_reg_alloc(&mut intervals, 8).into_iter().collect();
-    result.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+    let mut frame = Frame::from_lvar_map(HashMap::new());
+    let mut result: Vec<(VirtualReg, Location)> = linear_reg_alloc(&mut intervals, 8, &mut frame).into_iter().collect();
+    result.sort_by_key(|a| a.0.id);
 
     let expected = vec![
-        (VirtualReg { id: 0 }, 0), 
-        (VirtualReg { id: 1 }, 1), 
-        (VirtualReg { id: 2 }, 0), 
-        (VirtualReg { id: 3 }, 1), 
-        (VirtualReg { id: 4 }, 0),
+        (VirtualReg { id: 0 }, Location::Reg(0)), 
+        (VirtualReg { id: 1 }, Location::Reg(1)), 
+        (VirtualReg { id: 2 }, Location::Reg(0)), 
+        (VirtualReg { id: 3 }, Location::Reg(1)), 
+        (VirtualReg { id: 4 }, Location::Reg(0)),
     ];
     
     assert_eq!(result, expected);
@@ -110,16 +140,83 @@ fn test_alloc_longer_op2() {
         Interval { vreg: VirtualReg { id: 4 }, start: 4, end: 4, reg: None }, 
     ];
 
-    let mut result: Vec<(VirtualReg, usize)> = linear_reg_alloc(&mut intervals, 8).into_iter().collect();
-    result.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+    let mut frame = Frame::from_lvar_map(HashMap::new());
+    let mut result: Vec<(VirtualReg, Location)> = linear_reg_alloc(&mut intervals, 8, &mut frame).into_iter().collect();
+    result.sort_by_key(|a| a.0.id);
     
     let expected = vec![
-        (VirtualReg { id: 0 }, 0), 
-        (VirtualReg { id: 1 }, 1),
-        (VirtualReg { id: 2 }, 2), 
-        (VirtualReg { id: 3 }, 1),
-        (VirtualReg { id: 4 }, 0), 
+        (VirtualReg { id: 0 }, Location::Reg(0)), 
+        (VirtualReg { id: 1 }, Location::Reg(1)),
+        (VirtualReg { id: 2 }, Location::Reg(2)), 
+        (VirtualReg { id: 3 }, Location::Reg(1)),
+        (VirtualReg { id: 4 }, Location::Reg(0)), 
     ];
     
     assert_eq!(result, expected);
-}
\ No newline at end of file
+}
+
+#[test]
+/// レジスタが2つのときに3つの区間が重なる場合
+/// - 終了が最も遅い区間がスピルされる
+fn test_alloc_spill() {
+    let mut intervals = vec![
+        Interval { vreg: VirtualReg { id: 0 }, start: 0, end: 5, reg: None },
+        Interval { vreg: VirtualReg { id: 1 }, start: 1, end: 3, reg: None },
+        Interval { vreg: VirtualReg { id: 2 }, start: 2, end: 3, reg: None },
+        Interval { vreg: VirtualReg { id: 3 }, start: 3, end: 5, reg: None },
+    ];
+
+    let mut frame = Frame::from_lvar_map(HashMap::new());
+    let mut result: Vec<(VirtualReg, Location)> = linear_reg_alloc(&mut intervals, 2, &mut frame).into_iter().collect();
+    result.sort_by_key(|a| a.0.id);
+
+    let expected = vec![
+        (VirtualReg { id: 0 }, Location::Spill(8)),
+        (VirtualReg { id: 1 }, Location::Reg(1)),
+        (VirtualReg { id: 2 }, Location::Reg(0)),
+        (VirtualReg { id: 3 }, Location::Reg(0)),
+    ];
+
+    assert_eq!(result, expected);
+    assert_eq!(frame.stack_size(), 16);
+}
+
+#[test]
+/// 変数の仮想レジスタには割り当てない
+fn test_alloc_skip_var() {
+    let mut intervals = vec![
+        Interval { vreg: VirtualReg { id: 0 }, start: 0, end: 2, reg: None },
+        Interval { vreg: VirtualReg { id: 1 }, start: 1, end: 2, reg: None },
+    ];
+
+    let lvar_map = HashMap::from([(String::from("a"), VirtualReg { id: 0 })]);
+    let mut frame = Frame::from_lvar_map(lvar_map);
+    let result = linear_reg_alloc(&mut intervals, 8, &mut frame);
+
+    let expected = HashMap::from([(VirtualReg { id: 1 }, Location::Reg(0))]);
+    assert_eq!(result, expected);
+}
+
+#[test]
+/// 同じ位置から始まる区間は, すぐ終わるものともレジスタを共有しない
+/// - 関数の引数は全て同じ位置で定義されるので, 使われない引数が他の引数を壊さない
+/// - 後の位置から始まる区間は終わった区間のレジスタを使える
+fn test_alloc_same_start() {
+    let mut intervals = vec![
+        Interval { vreg: VirtualReg { id: 0 }, start: 0, end: 0, reg: None },
+        Interval { vreg: VirtualReg { id: 1 }, start: 0, end: 2, reg: None },
+        Interval { vreg: VirtualReg { id: 2 }, start: 1, end: 2, reg: None },
+    ];
+
+    let mut frame = Frame::from_lvar_map(HashMap::new());
+    let mut result: Vec<(VirtualReg, Location)> = linear_reg_alloc(&mut intervals, 8, &mut frame).into_iter().collect();
+    result.sort_by_key(|a| a.0.id);
+
+    let expected = vec![
+        (VirtualReg { id: 0 }, Location::Reg(0)),
+        (VirtualReg { id: 1 }, Location::Reg(1)),
+        (VirtualReg { id: 2 }, Location::Reg(0)),
+    ];
+
+    assert_eq!(result, expected);
+}
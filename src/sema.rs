@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::PResult;
+use crate::types::{BinOp, Expr, ExprKind, Span, Stmt, StmtKind, Type};
+
+/// 関数の型とローカル変数
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnInfo {
+    /// `()`で宣言しただけのときは`None` (引数を調べない)
+    pub params: Option<Vec<Type>>,
+    /// 可変長引数を取る (`params`より多く渡してよい)
+    pub variadic: bool,
+    pub ret: Type,
+    /// 引数を含むローカル変数の型
+    pub locals: HashMap<String, Type>,
+}
+
+/// グローバル変数
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalVar {
+    pub name: String,
+    pub ty: Type,
+    /// 初期値 (無ければ`.bss`に置く)
+    pub init: Option<i32>,
+    /// `extern`宣言 (領域は確保しない)
+    pub is_extern: bool,
+}
+
+/// 型付きの記号表
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    pub functions: HashMap<String, FnInfo>,
+    /// 宣言順のグローバル変数
+    pub globals: Vec<GlobalVar>,
+}
+
+impl SymbolTable {
+    /// 名前でグローバル変数を探す
+    pub fn global(&self, name: &str) -> Option<&GlobalVar> {
+        self.globals.iter().find(|g| g.name == name)
+    }
+}
+
+/// 型検査を行い, 全ての式に型を付ける
+/// - `*`の対象がポインタでない, キャスト無しでポインタと整数を代入する,
+///   関数の引数の数が合わない, といったプログラムを弾く
+/// - エラーがあっても文単位で続けて, 見つかった全てのエラーを返す
+pub fn check(program: &mut [Stmt]) -> Result<SymbolTable, Vec<Diagnostic>> {
+    let mut checker = Checker::default();
+
+    // 関数の型を先に登録しておく
+    // 今は戻り値はintのみ
+    // 同じ関数の宣言と定義は引数の型が揃っている必要がある
+    // `()`の宣言は引数を決めないので, 後の宣言や定義で引数が決まる
+    for stmt in program.iter() {
+        let (fn_name, params, variadic) = match &stmt.kind {
+            StmtKind::Fn { fn_name, params, .. } => {
+                // 引数の型はパーサで付けてある
+                let params = params.iter().map(|p| p.ty.clone().unwrap()).collect();
+                (fn_name, Some(params), false)
+            }
+            StmtKind::FnDecl { fn_name, params, variadic } => (fn_name, params.clone(), *variadic),
+            _ => continue,
+        };
+        match checker.table.functions.get_mut(fn_name) {
+            Some(info) if info.params.is_none() => {
+                info.params = params;
+                info.variadic = variadic;
+            }
+            Some(_) if params.is_none() => (),
+            Some(info) if info.params != params || info.variadic != variadic => {
+                let msg = format!("conflicting types for '{}'", fn_name);
+                checker.errors.push(Diagnostic::error(ErrorCode::Redefinition, msg, stmt.span));
+            }
+            Some(_) => (),
+            None => {
+                let info = FnInfo { params, variadic, ret: Type::Int, locals: HashMap::new() };
+                checker.table.functions.insert(fn_name.clone(), info);
+            }
+        }
+    }
+
+    for stmt in program.iter_mut() {
+        checker.stmt(stmt);
+    }
+
+    if checker.errors.is_empty() {
+        Ok(checker.table)
+    } else {
+        Err(checker.errors)
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    table: SymbolTable,
+    /// 検査中の関数のローカル変数
+    locals: HashMap<String, Type>,
+    /// 検査中の関数の戻り値の型
+    ret: Option<Type>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::ExprStmt(expr) => {
+                self.expr_stmt(expr);
+            }
+            StmtKind::Return(expr) => {
+                self.return_value(expr);
+            }
+            StmtKind::If { cond, then, els } => {
+                self.cond(cond);
+                self.stmt(then);
+                if let Some(els) = els {
+                    self.stmt(els);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.cond(cond);
+                self.stmt(body);
+            }
+            StmtKind::For { init, cond, update, body } => {
+                for expr in [init, update].into_iter().flatten() {
+                    self.expr_stmt(expr);
+                }
+                if let Some(cond) = cond {
+                    self.cond(cond);
+                }
+                self.stmt(body);
+            }
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+            }
+            StmtKind::Fn { fn_name, params, body } => {
+                self.locals.clear();
+                self.ret = self.table.functions.get(fn_name).map(|info| info.ret.clone());
+                for param in params.iter() {
+                    if let (ExprKind::Var(name), Some(ty)) = (&param.kind, &param.ty) {
+                        self.locals.insert(name.clone(), ty.clone());
+                    }
+                }
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                if let Some(info) = self.table.functions.get_mut(fn_name) {
+                    info.locals = std::mem::take(&mut self.locals);
+                }
+            }
+            // 関数の型は最初に登録済み
+            StmtKind::FnDecl { .. } => (),
+            StmtKind::VarDecl { name, ty } => {
+                self.check_complete(ty, stmt.span);
+                self.locals.insert(name.clone(), ty.clone());
+            }
+            StmtKind::GlobalVar { name, ty, init, is_extern } => {
+                // `extern`宣言は領域を確保しないので不完全型でもよい
+                if !*is_extern {
+                    self.check_complete(ty, stmt.span);
+                }
+                if let Some(value) = init {
+                    // 整数の定数で初期化できるのは整数とヌルポインタのみ
+                    let msg = match ty {
+                        Type::Int | Type::Char => None,
+                        Type::Ptr(_) if *value == 0 => None,
+                        Type::Ptr(_) => Some(format!("incompatible integer to pointer conversion initializing '{}' with an expression of type 'int'", ty)),
+                        Type::Array(..) => Some("array initializer must be an initializer list".to_string()),
+                        Type::Struct(_) => Some(format!("initializing '{}' with an expression of incompatible type 'int'", ty)),
+                    };
+                    if let Some(msg) = msg {
+                        self.errors.push(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, stmt.span));
+                    }
+                }
+                let global = GlobalVar { name: name.clone(), ty: ty.clone(), init: *init, is_extern: *is_extern };
+                self.table.globals.push(global);
+            }
+        }
+    }
+
+    /// 変数の型が不完全型でないか調べる
+    /// - 大きさが決まらないため, 定義前の構造体はポインタでしか使えない
+    fn check_complete(&mut self, ty: &Type, span: Span) {
+        if ty.is_incomplete() {
+            let msg = format!("variable has incomplete type '{}'", ty);
+            self.errors.push(Diagnostic::error(ErrorCode::IncompleteType, msg, span));
+        }
+    }
+
+    /// 文の中の式を検査する
+    /// - エラーは記録して次の文に進む
+    fn expr_stmt(&mut self, expr: &mut Expr) {
+        if let Err(e) = self.expr(expr) {
+            self.errors.push(e);
+        }
+    }
+
+    /// 戻り値の式を検査する
+    /// - 代入と同じ規則で戻り値の型に合わせる
+    fn return_value(&mut self, expr: &mut Expr) {
+        let ty = match self.expr(expr) {
+            Ok(ty) => ty.decay(),
+            Err(e) => {
+                self.errors.push(e);
+                return;
+            }
+        };
+        // 関数の外にreturn文は書けないので, 必ず戻り値の型がある
+        let ret = self.ret.clone().unwrap();
+        if let Some(mismatch) = mismatch(&ret, &ty, expr) {
+            let msg = match mismatch {
+                Mismatch::Pointers => format!("incompatible pointer types returning '{}' from a function with result type '{}'", ty, ret),
+                Mismatch::IntToPointer => format!("incompatible integer to pointer conversion returning '{}' from a function with result type '{}'", ty, ret),
+                Mismatch::PointerToInt => format!("incompatible pointer to integer conversion returning '{}' from a function with result type '{}'", ty, ret),
+                Mismatch::Other => format!("returning '{}' from a function with incompatible result type '{}'", ty, ret),
+            };
+            self.errors.push(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, expr.span));
+        }
+    }
+
+    /// 条件式を検査する
+    /// - 構造体は真偽を決められないので弾く
+    /// - 配列は先頭のアドレスになって常に真になるため, 誤りとして弾く
+    fn cond(&mut self, cond: &mut Expr) {
+        match self.expr(cond) {
+            Ok(Type::Int | Type::Char | Type::Ptr(_)) => (),
+            Ok(ty @ (Type::Array(..) | Type::Struct(_))) => {
+                let msg = format!("statement requires expression of scalar type ('{}' invalid)", ty);
+                self.errors.push(Diagnostic::error(ErrorCode::InvalidOperands, msg, cond.span));
+            }
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    /// 式の型を求めて`Expr::ty`に書き込む
+    fn expr(&mut self, expr: &mut Expr) -> PResult<Type> {
+        let span = expr.span;
+        let ty = match &mut expr.kind {
+            ExprKind::Num(_) => Type::Int,
+            ExprKind::Str(_) => Type::Ptr(Box::new(Type::Char)),
+            // ローカル変数を優先する
+            ExprKind::Var(name) => match self.locals.get(name).or(self.table.global(name).map(|g| &g.ty)) {
+                Some(ty) => ty.clone(),
+                None => {
+                    let msg = format!("Use of undeclared identifier '{}'", name);
+                    return Err(Diagnostic::error(ErrorCode::UndeclaredVar, msg, span));
+                }
+            },
+            ExprKind::Binary { op, lhs, rhs } => {
+                let l = self.expr(lhs)?.decay();
+                let r = self.expr(rhs)?.decay();
+                binary_type(op, &l, &r, span)?
+            }
+            ExprKind::Assign { lhs, rhs } => {
+                let l = self.expr(lhs)?;
+                if l.is_incomplete() {
+                    let msg = format!("incomplete type '{}' is not assignable", l);
+                    return Err(Diagnostic::error(ErrorCode::IncompleteType, msg, lhs.span));
+                }
+                if let Type::Array(..) = l {
+                    let msg = format!("array type '{}' is not assignable", l);
+                    return Err(Diagnostic::error(ErrorCode::NotAssignable, msg, lhs.span));
+                }
+                let r = self.expr(rhs)?.decay();
+                if let Some(mismatch) = mismatch(&l, &r, rhs) {
+                    let msg = match mismatch {
+                        Mismatch::Pointers => format!("incompatible pointer types assigning to '{}' from '{}'", l, r),
+                        Mismatch::IntToPointer => format!("incompatible integer to pointer conversion assigning to '{}' from '{}'", l, r),
+                        Mismatch::PointerToInt => format!("incompatible pointer to integer conversion assigning to '{}' from '{}'", l, r),
+                        Mismatch::Other => format!("assigning to '{}' from incompatible type '{}'", l, r),
+                    };
+                    return Err(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, span));
+                }
+                l
+            }
+            ExprKind::Call { fn_name, args } => {
+                let mut arg_types = Vec::new();
+                for arg in args.iter_mut() {
+                    let ty = self.expr(arg)?.decay();
+                    // 構造体は値渡しできない
+                    if let Type::Struct(_) = ty {
+                        let msg = format!("passing '{}' by value is not supported", ty);
+                        return Err(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, arg.span));
+                    }
+                    arg_types.push(ty);
+                }
+                let info = match self.table.functions.get(fn_name) {
+                    Some(info) => info,
+                    None => return Err(Diagnostic::error(ErrorCode::UndefinedFn, "宣言されていない関数を呼び出しています", span)),
+                };
+                // `()`で宣言した関数は引数を調べない (引数の無い可変長引数と同じ)
+                let (params, variadic) = match &info.params {
+                    Some(params) => (params.as_slice(), info.variadic),
+                    None => (&[][..], true),
+                };
+                // 可変長引数の関数には多く渡してよい
+                if args.len() < params.len() || (args.len() > params.len() && !variadic) {
+                    let few_or_many = if args.len() < params.len() { "few" } else { "many" };
+                    let at_least = if variadic { "at least " } else { "" };
+                    let msg = format!("too {} arguments to function call, expected {}{}, have {}", few_or_many, at_least, params.len(), args.len());
+                    return Err(Diagnostic::error(ErrorCode::ArgCountMismatch, msg, span));
+                }
+                // 引数は代入と同じ規則で引数の型に変換できる必要がある
+                // 可変長引数の部分は調べない
+                for ((param, arg), ty) in params.iter().zip(args.iter()).zip(&arg_types) {
+                    if let Some(mismatch) = mismatch(param, ty, arg) {
+                        let msg = match mismatch {
+                            Mismatch::Pointers => format!("incompatible pointer types passing '{}' to parameter of type '{}'", ty, param),
+                            Mismatch::IntToPointer => format!("incompatible integer to pointer conversion passing '{}' to parameter of type '{}'", ty, param),
+                            Mismatch::PointerToInt => format!("incompatible pointer to integer conversion passing '{}' to parameter of type '{}'", ty, param),
+                            Mismatch::Other => format!("passing '{}' to parameter of incompatible type '{}'", ty, param),
+                        };
+                        return Err(Diagnostic::error(ErrorCode::IncompatibleTypes, msg, arg.span));
+                    }
+                }
+                info.ret.clone()
+            }
+            ExprKind::Addr(var) => {
+                let ty = self.expr(var)?;
+                match &var.kind {
+                    // `&*e`は`e`と同じ
+                    ExprKind::Deref(addr) => addr.ty.clone().map_or(ty, |t| t.decay()),
+                    _ => Type::Ptr(Box::new(ty)),
+                }
+            }
+            ExprKind::Deref(addr) => {
+                match self.expr(addr)?.decay() {
+                    Type::Ptr(base) => *base,
+                    ty => {
+                        let msg = format!("indirection requires pointer operand ('{}' invalid)", ty);
+                        return Err(Diagnostic::error(ErrorCode::NotDereferenceable, msg, span));
+                    }
+                }
+            }
+            ExprKind::Member { base, name } => {
+                match self.expr(base)? {
+                    Type::Struct(agg) if !agg.is_complete() => {
+                        let msg = format!("incomplete definition of type '{}'", agg);
+                        return Err(Diagnostic::error(ErrorCode::IncompleteType, msg, span));
+                    }
+                    Type::Struct(agg) => match agg.member(name) {
+                        Some(member) => member.ty,
+                        None => {
+                            let msg = format!("no member named '{}' in '{}'", name, agg);
+                            return Err(Diagnostic::error(ErrorCode::NoMember, msg, span));
+                        }
+                    },
+                    ty => {
+                        let msg = format!("member reference base type '{}' is not a structure or union", ty);
+                        return Err(Diagnostic::error(ErrorCode::NoMember, msg, span));
+                    }
+                }
+            }
+        };
+        expr.ty = Some(ty.clone());
+        Ok(ty)
+    }
+}
+
+/// 代入 (引数の受け渡しを含む) で型が食い違う種類
+enum Mismatch {
+    /// 指す先の違うポインタ
+    Pointers,
+    IntToPointer,
+    PointerToInt,
+    /// ポインタ以外の食い違い (構造体など)
+    Other,
+}
+
+/// `rhs` (型は`r`) を`l`の型に代入できるか調べる
+/// - 0はヌルポインタとして代入できる
+/// - 整数同士 (intとchar) は暗黙に変換する
+fn mismatch(l: &Type, r: &Type, rhs: &Expr) -> Option<Mismatch> {
+    let is_null = matches!(rhs.kind, ExprKind::Num(0));
+    let is_integers = l.is_integer() && r.is_integer();
+    if l == r || (l.is_ptr() && is_null) || is_integers {
+        return None;
+    }
+    Some(match (l.is_ptr(), r.is_ptr()) {
+        (true, true) => Mismatch::Pointers,
+        (true, false) => Mismatch::IntToPointer,
+        (false, true) => Mismatch::PointerToInt,
+        (false, false) => Mismatch::Other,
+    })
+}
+
+/// 二項演算の結果の型
+/// - 整数同士の演算はcharもintとして計算する
+/// - ポインタと整数の足し算, 引き算はポインタになる
+/// - 同じ型のポインタ同士の引き算は整数になる
+fn binary_type(op: &BinOp, l: &Type, r: &Type, span: Span) -> PResult<Type> {
+    // 大きさの分からない型へのポインタは足し引きできない
+    if matches!(op, BinOp::Add | BinOp::Sub) {
+        for ty in [l, r] {
+            if let Type::Ptr(base) = ty && base.is_incomplete() {
+                let msg = format!("arithmetic on a pointer to an incomplete type '{}'", base);
+                return Err(Diagnostic::error(ErrorCode::IncompleteType, msg, span));
+            }
+        }
+    }
+    // 構造体は演算に使えない
+    let is_scalar = |ty: &Type| ty.is_integer() || ty.is_ptr();
+    let ty = match op {
+        _ if !is_scalar(l) || !is_scalar(r) => None,
+        BinOp::Add => match (l.is_ptr(), r.is_ptr()) {
+            (false, false) => Some(Type::Int),
+            (true, false) => Some(l.clone()),
+            (false, true) => Some(r.clone()),
+            (true, true) => None,
+        },
+        BinOp::Sub => match (l.is_ptr(), r.is_ptr()) {
+            (false, false) => Some(Type::Int),
+            (true, false) => Some(l.clone()),
+            (true, true) if l == r => Some(Type::Int),
+            (true, true) | (false, true) => None,
+        },
+        BinOp::Mul | BinOp::Div => match (l.is_ptr(), r.is_ptr()) {
+            (false, false) => Some(Type::Int),
+            _ => None,
+        },
+        // 比較の結果は0か1
+        BinOp::Le | BinOp::Lt | BinOp::Eq | BinOp::Ne => Some(Type::Int),
+    };
+    ty.ok_or_else(|| {
+        let msg = format!("invalid operands to binary expression ('{}' and '{}')", l, r);
+        Diagnostic::error(ErrorCode::InvalidOperands, msg, span)
+    })
+}
+
+#[cfg(test)]
+fn check_src(input: &str) -> Result<(Vec<Stmt>, SymbolTable), Vec<Diagnostic>> {
+    use crate::lexer::{TokenStream, Tokenizer};
+    use crate::parser::Parser;
+
+    let tok_vec = Tokenizer::new(input).tokenize().unwrap();
+    let mut parser = Parser::new(TokenStream::new(tok_vec, input));
+    let mut program = parser.program().unwrap();
+    let table = check(&mut program)?;
+    Ok((program, table))
+}
+
+#[test]
+/// 式に型を付けて記号表を作る
+fn test_annotate_types() {
+    let (program, table) = check_src("int main() { int x; int *p; p = &x; return *p + 1; }").unwrap();
+
+    let main = &table.functions["main"];
+    assert_eq!(main.locals["p"], Type::Ptr(Box::new(Type::Int)));
+    assert_eq!(main.ret, Type::Int);
+
+    let StmtKind::Fn { body, .. } = &program[0].kind else { panic!() };
+    let StmtKind::ExprStmt(assign) = &body[2].kind else { panic!() };
+    assert_eq!(assign.ty, Some(Type::Ptr(Box::new(Type::Int))));
+    let StmtKind::Return(ret) = &body[3].kind else { panic!() };
+    assert_eq!(ret.ty, Some(Type::Int));
+}
+
+#[test]
+/// 整数の参照外しを弾く
+fn test_deref_int() {
+    let errors = check_src("int main() { int x; return *x; }").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::NotDereferenceable);
+    assert_eq!(errors[0].message, "indirection requires pointer operand ('int' invalid)");
+}
+
+#[test]
+/// キャスト無しのポインタと整数の代入を弾く
+fn test_pointer_int_assign() {
+    let errors = check_src("int main() { int x; int *p; x = &x; p = 1; p = 0; return x; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "incompatible pointer to integer conversion assigning to 'int' from 'int *'",
+        "incompatible integer to pointer conversion assigning to 'int *' from 'int'",
+    ]);
+}
+
+#[test]
+/// 引数の数が合わない呼び出しを弾く
+fn test_arg_count() {
+    let errors = check_src("int add(int a, int b) { return a + b; } int main() { return add(1); }").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::ArgCountMismatch);
+    assert_eq!(errors[0].message, "too few arguments to function call, expected 2, have 1");
+}
+
+#[test]
+/// charは整数として計算・代入できる
+fn test_char() {
+    let (program, table) = check_src("int main() { char c; char *p; c = 1; p = &c; return *p + c; }").unwrap();
+
+    assert_eq!(table.functions["main"].locals["p"], Type::Ptr(Box::new(Type::Char)));
+    let StmtKind::Fn { body, .. } = &program[0].kind else { panic!() };
+    let StmtKind::Return(ret) = &body[4].kind else { panic!() };
+    assert_eq!(ret.ty, Some(Type::Int));
+
+    let errors = check_src("int main() { char c; int *p; p = &c; return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "incompatible pointer types assigning to 'int *' from 'char *'");
+}
+
+#[test]
+/// 文字列リテラルは`char *`になる
+fn test_string_literal() {
+    let (program, _) = check_src("int main() { char *s; s = \"abc\"; return *s; }").unwrap();
+    let StmtKind::Fn { body, .. } = &program[0].kind else { panic!() };
+    let StmtKind::ExprStmt(assign) = &body[1].kind else { panic!() };
+    assert_eq!(assign.ty, Some(Type::Ptr(Box::new(Type::Char))));
+
+    let errors = check_src("int main() { int *p; p = \"abc\"; return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "incompatible pointer types assigning to 'int *' from 'char *'");
+}
+
+#[test]
+/// 配列は式の中でポインタになり, 代入はできない
+fn test_array() {
+    let (program, _) = check_src("int main() { int a[3]; int *p; p = a; return *(a + 1); }").unwrap();
+    let StmtKind::Fn { body, .. } = &program[0].kind else { panic!() };
+    let StmtKind::ExprStmt(assign) = &body[2].kind else { panic!() };
+    assert_eq!(assign.ty, Some(Type::Ptr(Box::new(Type::Int))));
+
+    let errors = check_src("int main() { int a[3]; int b[3]; a = b; return 0; }").unwrap_err();
+    assert_eq!(errors[0].code, ErrorCode::NotAssignable);
+    assert_eq!(errors[0].message, "array type 'int [3]' is not assignable");
+
+    let errors = check_src("int main() { int a[2][3]; int *p; p = a; return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "incompatible pointer types assigning to 'int *' from 'int (*)[3]'");
+}
+
+#[test]
+/// 構造体のメンバに型を付け, 無いメンバの参照を弾く
+fn test_member() {
+    let (program, _) = check_src("int main() { struct P { int x; char c; } p; struct P *q; q = &p; return q->c; }").unwrap();
+    let StmtKind::Fn { body, .. } = &program[0].kind else { panic!() };
+    let StmtKind::Return(ret) = &body[3].kind else { panic!() };
+    assert_eq!(ret.ty, Some(Type::Char));
+
+    let errors = check_src("int main() { struct P { int x; } p; int y; p.y = 1; y.x = 1; p = 1; return 0; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "no member named 'y' in 'struct P'",
+        "member reference base type 'int' is not a structure or union",
+        "assigning to 'struct P' from incompatible type 'int'",
+    ]);
+}
+
+#[test]
+/// 定義前の構造体はポインタとしてだけ使える
+fn test_incomplete_struct() {
+    check_src("struct S; extern struct S e; struct S *p; int main() { struct S *q; q = p; return q == 0; }").unwrap();
+
+    let errors = check_src("struct S; struct S g; int main() { struct S s; struct S *p; p->x = 1; *p = *p; p = p + 1; return 0; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "variable has incomplete type 'struct S'",
+        "variable has incomplete type 'struct S'",
+        "incomplete definition of type 'struct S'",
+        "incomplete type 'struct S' is not assignable",
+        "arithmetic on a pointer to an incomplete type 'struct S'",
+    ]);
+    assert!(errors.iter().all(|e| e.code == ErrorCode::IncompleteType));
+}
+
+#[test]
+/// 戻り値は代入と同じ規則で戻り値の型に合わせる
+fn test_return_type() {
+    check_src("int main() { char c; c = 1; return c; }").unwrap();
+
+    let errors = check_src("struct S { int a; }; int f() { int *p; return p; } int g() { struct S s; return s; } int h() { int a[2]; return a; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "incompatible pointer to integer conversion returning 'int *' from a function with result type 'int'",
+        "returning 'struct S' from a function with incompatible result type 'int'",
+        "incompatible pointer to integer conversion returning 'int *' from a function with result type 'int'",
+    ]);
+    assert!(errors.iter().all(|e| e.code == ErrorCode::IncompatibleTypes));
+}
+
+#[test]
+/// 条件式は整数かポインタで, 構造体と配列は弾く
+fn test_cond_scalar() {
+    check_src("int main() { int *p; p = 0; char c; c = 0; if (p) return 1; while (c) return 2; for (; p;) return 3; return 0; }").unwrap();
+
+    let errors = check_src("struct S { int a; }; int main() { struct S s; int a[2]; if (s) return 1; while (a) return 2; for (; s;) return 3; return 0; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "statement requires expression of scalar type ('struct S' invalid)",
+        "statement requires expression of scalar type ('int [2]' invalid)",
+        "statement requires expression of scalar type ('struct S' invalid)",
+    ]);
+    assert!(errors.iter().all(|e| e.code == ErrorCode::InvalidOperands));
+}
+
+#[test]
+/// グローバル変数を記号表に入れ, ローカル変数を優先して型を付ける
+fn test_global() {
+    let (program, table) = check_src("int *g; int main() { int x; x = *g; int g; g = 1; return g; }").unwrap();
+    assert_eq!(table.global("g").unwrap().ty, Type::Ptr(Box::new(Type::Int)));
+    let StmtKind::Fn { body, .. } = &program[1].kind else { panic!() };
+    let StmtKind::ExprStmt(assign) = &body[3].kind else { panic!() };
+    assert_eq!(assign.ty, Some(Type::Int));
+
+    let errors = check_src("int *g = 1; int main() { return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "incompatible integer to pointer conversion initializing 'int *' with an expression of type 'int'");
+}
+
+#[test]
+/// プロトタイプ宣言の引数の数で呼び出しを調べ, 定義と型が合わなければ弾く
+fn test_prototype() {
+    let (_, table) = check_src("int printf(char *fmt, ...); int f(int a); int main() { return printf(\"%d\", f(1)); } int f(int a) { return a; }").unwrap();
+    assert_eq!(table.functions["printf"].params, Some(vec![Type::Ptr(Box::new(Type::Char))]));
+    assert!(table.functions["printf"].variadic);
+
+    let errors = check_src("int printf(char *fmt, ...); int main() { return printf(); }").unwrap_err();
+    assert_eq!(errors[0].message, "too few arguments to function call, expected at least 1, have 0");
+
+    let errors = check_src("int f(int *p); int f(int a) { return a; } int main() { return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "conflicting types for 'f'");
+}
+
+#[test]
+/// 定義の引数は宣言した型になり, 配列はポインタになる
+fn test_param_types() {
+    let int_ptr = Type::Ptr(Box::new(Type::Int));
+    let (_, table) = check_src("int f(int *p, int a[2]); int f(int *p, int a[2]) { return *p + a[1]; } int main() { int x; return f(&x, &x); }").unwrap();
+    assert_eq!(table.functions["f"].params, Some(vec![int_ptr.clone(), int_ptr.clone()]));
+    assert_eq!(table.functions["f"].locals["a"], int_ptr);
+
+    let errors = check_src("int f(int a); int f(char *p) { return 0; } int main() { return 0; }").unwrap_err();
+    assert_eq!(errors[0].message, "conflicting types for 'f'");
+
+    let errors = check_src("int f(int *p) { return *p; } int main() { return f(1); }").unwrap_err();
+    assert_eq!(errors[0].code, ErrorCode::IncompatibleTypes);
+}
+
+#[test]
+/// `()`の宣言は引数を決めず, 後の定義と食い違わない
+fn test_unspecified_params() {
+    let (_, table) = check_src("int f(); int g(); int main() { return f(1) + g(1, 2); } int f(int a) { return a; }").unwrap();
+    assert_eq!(table.functions["f"].params, Some(vec![Type::Int]));
+    assert_eq!(table.functions["g"].params, None);
+
+    check_src("int f(int a); int f(); int main() { return 0; }").unwrap();
+}
+
+#[test]
+/// 引数は代入と同じ規則で引数の型に合わせ, 構造体は渡せない
+fn test_arg_types() {
+    check_src("int g(int *p, char c); int main() { int x; char c; g(&x, x); g(0, c); return 0; }").unwrap();
+
+    let errors = check_src("struct S { int a; }; int g(int *p, int x); int printf(char *fmt, ...); \
+        int main() { struct S s; char c; g(&c, 1); g(1, 1); g(0, &c); g(0, s); printf(\"%d\", s); return 0; }").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "incompatible pointer types passing 'char *' to parameter of type 'int *'",
+        "incompatible integer to pointer conversion passing 'int' to parameter of type 'int *'",
+        "incompatible pointer to integer conversion passing 'char *' to parameter of type 'int'",
+        "passing 'struct S' by value is not supported",
+        "passing 'struct S' by value is not supported",
+    ]);
+    assert!(errors.iter().all(|e| e.code == ErrorCode::IncompatibleTypes));
+}
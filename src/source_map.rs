@@ -0,0 +1,55 @@
+/// ソースコード上の位置を行と列に変換する
+/// - 行と列は1から数える
+#[derive(Debug, Clone)]
+pub struct SourceMap<'a> {
+    pub name: &'a str,
+    pub src: &'a str,
+    /// 各行の先頭のオフセット
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(name: &'a str, src: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, c) in src.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceMap { name, src, line_starts }
+    }
+
+    /// オフセットを (行, 列) に変換する
+    /// - 列は行の先頭からのバイト数で数える
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        // posより後ろから始まる最初の行の1つ前
+        let line = self.line_starts.partition_point(|&start| start <= pos) - 1;
+        (line + 1, pos - self.line_starts[line] + 1)
+    }
+
+    /// 行の中身を返す (改行は含まない)
+    pub fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next) => next - 1,
+            None => self.src.len(),
+        };
+        self.src.get(start..end).unwrap_or("").trim_end_matches('\r')
+    }
+}
+
+#[test]
+/// 複数行の位置を変換する
+fn test_line_col() {
+    let src = "int main() {\n  return 1;\n}\n";
+    let map = SourceMap::new("a.c", src);
+
+    assert_eq!(map.line_col(0), (1, 1));
+    assert_eq!(map.line_col(src.find("return").unwrap()), (2, 3));
+    assert_eq!(map.line_col(src.find('}').unwrap()), (3, 1));
+    // EOFは最後の改行の後ろ
+    assert_eq!(map.line_col(src.len()), (4, 1));
+
+    assert_eq!(map.line_text(2), "  return 1;");
+    assert_eq!(map.line_text(4), "");
+}
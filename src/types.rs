@@ -1,8 +1,15 @@
 #![allow(non_camel_case_types)]
 
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TypeKind {
     Int,
+    Char,
+    Struct,
+    Union,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -12,9 +19,11 @@ pub enum TokenKind {
     TK_FOR,      // for
     TK_IF,       // if
     TK_ELSE,     // else
+    TK_EXTERN,   // extern
     TK_RESERVED, // 記号
     TK_IDENT,    // 変数名の識別子
-    TK_NUM,      // 整数
+    TK_NUM,      // 整数 (文字リテラルも含む)
+    TK_STR(Vec<u8>), // 文字列リテラル (エスケープを展開したバイト列, 終端の0は含まない)
     TK_TYPE(TypeKind), // 型
     TK_EOF,      // 入力の終わり
 }
@@ -38,6 +47,18 @@ impl Token {
             pos,
         }
     }
+
+    /// トークンが占めるソースコード上の範囲
+    pub fn span(&self) -> Span {
+        Span { start: self.pos, end: self.pos + self.len }
+    }
+}
+
+/// ソースコード上の範囲 (先頭からのオフセット, endは含まない)
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, PartialEq)]
@@ -46,15 +67,209 @@ pub enum BinOp {
     Le, Lt, Eq, Ne,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Type {
     Int,
+    Char,
     Ptr(Box<Type>),
+    Array(Box<Type>, usize), // 要素の型と要素数
+    Struct(Rc<Aggregate>), // 構造体と共用体
+}
+
+/// 構造体・共用体のメンバ
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Member {
+    pub name: String,
+    pub ty: Type,
+    /// 先頭からのオフセット (バイト)
+    pub offset: usize,
+}
+
+/// 構造体・共用体の型とそのレイアウト
+/// - 同じ宣言から作られた型だけを同じ型として扱う (`Rc`で共有して比べる)
+/// - `struct Node { struct Node *next; }`のように定義の中から参照できるよう,
+///   タグを登録した後で`}`まで読んでからメンバを決める (それまでは不完全型)
+pub struct Aggregate {
+    pub tag: Option<String>,
+    pub is_union: bool,
+    /// 不完全型のときは`None`
+    layout: RefCell<Option<Layout>>,
+}
+
+/// メンバの配置と全体の大きさ・境界
+#[derive(Debug, Clone)]
+struct Layout {
+    members: Vec<Member>,
+    size: usize,
+    align: usize,
+}
+
+impl Aggregate {
+    /// メンバの決まっていない不完全型を作る
+    pub fn new(tag: Option<String>, is_union: bool) -> Self {
+        Aggregate { tag, is_union, layout: RefCell::new(None) }
+    }
+
+    /// メンバの配置を計算して完全型にする (SysV ABI)
+    /// - 構造体のメンバは宣言順に, それぞれの型の境界に揃えて並べる
+    /// - 共用体のメンバは全て先頭に置く
+    /// - 全体の境界はメンバの境界の最大値で, 大きさはその倍数に切り上げる
+    pub fn define(&self, members: Vec<(String, Type)>) {
+        let mut offset: usize = 0;
+        let mut size: usize = 0;
+        let mut align = 1;
+        let members = members.into_iter().map(|(name, ty)| {
+            align = align.max(ty.align());
+            let member_offset = if self.is_union { 0 } else { offset.next_multiple_of(ty.align()) };
+            offset = member_offset + ty.size();
+            size = size.max(offset);
+            Member { name, ty, offset: member_offset }
+        }).collect();
+        *self.layout.borrow_mut() = Some(Layout { members, size: size.next_multiple_of(align), align });
+    }
+
+    /// メンバが決まっているか調べる
+    pub fn is_complete(&self) -> bool {
+        self.layout.borrow().is_some()
+    }
+
+    /// 名前でメンバを探す
+    pub fn member(&self, name: &str) -> Option<Member> {
+        let layout = self.layout.borrow();
+        layout.as_ref()?.members.iter().find(|m| m.name == name).cloned()
+    }
+
+    /// 大きさ (バイト)
+    /// - 不完全型は0
+    pub fn size(&self) -> usize {
+        self.layout.borrow().as_ref().map_or(0, |l| l.size)
+    }
+
+    /// 境界 (バイト)
+    /// - 不完全型は1
+    pub fn align(&self) -> usize {
+        self.layout.borrow().as_ref().map_or(1, |l| l.align)
+    }
+}
+
+impl PartialEq for Aggregate {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Aggregate {}
+
+// メンバに自分へのポインタを持つことがあるため, 名前だけを表示する
+impl fmt::Debug for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
 }
 
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keyword = if self.is_union { "union" } else { "struct" };
+        match &self.tag {
+            Some(tag) => write!(f, "{} {}", keyword, tag),
+            None => write!(f, "{} (anonymous)", keyword),
+        }
+    }
+}
+
+impl Type {
+    /// 型の大きさ (バイト)
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Int => 4,
+            Type::Char => 1,
+            Type::Ptr(_) => 8,
+            Type::Array(base, len) => base.size() * len,
+            Type::Struct(agg) => agg.size(),
+        }
+    }
+
+    /// スタック上に置くときの境界 (バイト)
+    /// - 配列は要素の境界に揃える
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(base, _) => base.align(),
+            Type::Struct(agg) => agg.align(),
+            Type::Int | Type::Char | Type::Ptr(_) => self.size(),
+        }
+    }
+
+    pub fn is_ptr(&self) -> bool {
+        matches!(self, Type::Ptr(_))
+    }
+
+    /// 不完全型 (定義前の構造体とその配列) か調べる
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Type::Struct(agg) => !agg.is_complete(),
+            Type::Array(base, _) => base.is_incomplete(),
+            Type::Int | Type::Char | Type::Ptr(_) => false,
+        }
+    }
+
+    /// 整数型 (int, char) か調べる
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::Int | Type::Char)
+    }
+
+    /// 式の中の配列を先頭要素へのポインタに変換する
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(base, _) => Type::Ptr(base.clone()),
+            Type::Int | Type::Char | Type::Ptr(_) | Type::Struct(_) => self.clone(),
+        }
+    }
+
+    /// 配列の要素数を外側から並べた文字列と, 一番内側の要素の型
+    /// - `int [2][3]`なら`("[2][3]", int)`
+    fn array_dims(&self) -> (String, &Type) {
+        match self {
+            Type::Array(base, len) => {
+                let (dims, elem) = base.array_dims();
+                (format!("[{}]{}", len, dims), elem)
+            }
+            Type::Int | Type::Char | Type::Ptr(_) | Type::Struct(_) => (String::new(), self),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Ptr(base) => match **base {
+                Type::Ptr(_) => write!(f, "{}*", base),
+                Type::Int | Type::Char | Type::Struct(_) => write!(f, "{} *", base),
+                // 配列へのポインタは`int (*)[3]`と書く
+                Type::Array(..) => {
+                    let (dims, elem) = base.array_dims();
+                    write!(f, "{} (*){}", elem, dims)
+                }
+            },
+            Type::Array(..) => {
+                let (dims, elem) = self.array_dims();
+                match elem {
+                    Type::Ptr(_) => write!(f, "{}{}", elem, dims),
+                    Type::Int | Type::Char | Type::Array(..) | Type::Struct(_) => write!(f, "{} {}", elem, dims),
+                }
+            }
+            Type::Struct(agg) => write!(f, "{}", agg),
+        }
+    }
+}
+
+/// 式の種類
 #[derive(Debug, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Num(i32),
+    /// 文字列リテラル (終端の0は含まない)
+    Str(Vec<u8>),
     Var(String),
     Binary {
         op: BinOp,
@@ -71,10 +286,32 @@ pub enum Expr {
     },
     Addr (Box<Expr>),
     Deref (Box<Expr>),
+    /// メンバの参照 (`a.x`, `p->x`は`(*p).x`として表す)
+    Member {
+        base: Box<Expr>,
+        name: String,
+    },
 }
 
+/// 式とそのソースコード上の範囲
 #[derive(Debug, PartialEq)]
-pub enum Stmt {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// 式の型 (`sema::check`で埋める)
+    /// - 関数定義の引数は宣言した型をパーサで付ける
+    pub ty: Option<Type>,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span, ty: None }
+    }
+}
+
+/// 文の種類
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
     ExprStmt(Expr),
     Return(Expr),
     If {
@@ -98,8 +335,60 @@ pub enum Stmt {
         params: Vec<Expr>,
         body: Vec<Stmt>,
     },
+    /// 関数のプロトタイプ宣言 (戻り値は今はintのみ)
+    FnDecl {
+        fn_name: String,
+        /// `()`のときは`None` (引数の型と数を決めない)
+        params: Option<Vec<Type>>,
+        /// 可変長引数 (`...`) を取る
+        variadic: bool,
+    },
+    GlobalVar {
+        name: String,
+        ty: Type,
+        /// 初期値 (無ければ0で初期化される)
+        init: Option<i32>,
+        /// `extern`宣言 (領域は他のファイルで確保される)
+        is_extern: bool,
+    },
     VarDecl {
         name: String,
         ty: Type,
     },
-}
\ No newline at end of file
+}
+
+/// 文とそのソースコード上の範囲
+#[derive(Debug, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Stmt { kind, span }
+    }
+}
+
+#[test]
+/// メンバのオフセットと構造体の大きさ・境界を求める
+fn test_aggregate_layout() {
+    let members = vec![
+        ("c".to_string(), Type::Char),
+        ("x".to_string(), Type::Int),
+        ("p".to_string(), Type::Ptr(Box::new(Type::Int))),
+        ("d".to_string(), Type::Char),
+    ];
+    let s = Aggregate::new(Some("S".to_string()), false);
+    assert!(!s.is_complete());
+    s.define(members.clone());
+    let offsets: Vec<usize> = ["c", "x", "p", "d"].iter().map(|name| s.member(name).unwrap().offset).collect();
+    assert_eq!(offsets, vec![0, 4, 8, 16]);
+    assert_eq!((s.size(), s.align()), (24, 8));
+
+    let u = Aggregate::new(None, true);
+    u.define(members);
+    assert!(["c", "x", "p", "d"].iter().all(|name| u.member(name).unwrap().offset == 0));
+    assert_eq!((u.size(), u.align()), (8, 8));
+    assert_eq!(Type::Struct(Rc::new(u)).to_string(), "union (anonymous)");
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use nonicc::frame::Frame;
+use nonicc::gen_x86_64::Generator;
+use nonicc::ir::types_ir::{ VirtualReg, ThreeAddressCode::*, Param, Width };
+use nonicc::opt::pass_manager::PassManager;
+use nonicc::reg_alloc::register_allocation::Location;
+use nonicc::{ compile, compile_file, compile_with, Options };
+
+// ソースコードからアセンブリを生成する
+#[test]
+fn compile_return() {
+    let asm = compile("int main() { return 42; }").unwrap();
+
+    assert!(asm.starts_with(".intel_syntax noprefix\n"));
+    assert!(asm.contains(".globl main\nmain:\n"));
+    assert!(asm.contains("  mov rdi, 42\n"));
+    assert!(asm.ends_with("  ret\n"));
+}
+
+// 最適化すると定数が畳み込まれる
+#[test]
+fn compile_with_opt() {
+    let options = Options { pass_manager: PassManager::with_opt_level(1), ..Default::default() };
+    let asm = compile_with("int main() { return 1 + 2 * 3; }", &options).unwrap();
+
+    assert!(asm.contains(", 7\n"));
+    assert!(!asm.contains("imul"));
+}
+
+// -gを付けると行番号のデバッグ情報を出力する
+#[test]
+fn compile_debug_info() {
+    let options = Options { debug_info: true, ..Default::default() };
+    let asm = compile_file("a.c", "int main() {\n  int a;\n  a = 1;\n  return a;\n}\n", &options).unwrap();
+
+    assert!(asm.contains(".file 1 \"a.c\"\n"));
+    assert!(asm.contains("  .loc 1 3 3\n"));
+    assert!(asm.contains("  .loc 1 4 3\n"));
+}
+
+// intの変数は4バイトで読み書きして演算は32bitで行う
+#[test]
+fn compile_int_dword() {
+    let asm = compile("int main() { int a; a = 1; return a + 2; }").unwrap();
+
+    assert!(asm.contains("  mov DWORD PTR [rbp - 4], edi\n"));
+    assert!(asm.contains("  movsxd r10, DWORD PTR [rbp - 4]\n"));
+    assert!(asm.contains("  add eax, edi\n"));
+    // callee-savedのrbxは使わない
+    assert!(!asm.contains("rbx"));
+    assert!(asm.contains("  movsxd rdi, edi\n"));
+}
+
+// charの変数は1バイトで読み書きする
+#[test]
+fn compile_char_byte() {
+    let asm = compile("int main() { char c; c = 3; return c; }").unwrap();
+
+    assert!(asm.contains("  mov BYTE PTR [rsi], dil\n"));
+    assert!(asm.contains("  movsx rdi, BYTE PTR [rdi]\n"));
+}
+
+// グローバル変数はRIP相対で参照し, .data/.bssに置く
+#[test]
+fn compile_global() {
+    let asm = compile("int g; int h = 3; int main() { g = h; return g; }").unwrap();
+
+    assert!(asm.contains("  movsxd rdi, DWORD PTR [rip + h]\n"));
+    assert!(asm.contains("  mov DWORD PTR [rip + g], "));
+    assert!(asm.contains(".data\n.globl h\n.align 4\nh:\n  .long 3\n"));
+    assert!(asm.contains(".bss\n.globl g\n.align 4\ng:\n  .zero 4\n"));
+}
+
+// 文字列リテラルは.rodataに置いてラベルで参照する
+#[test]
+fn compile_string_literal() {
+    let asm = compile("int main() { char *s; s = \"hi\\n\"; s = \"\\\"\"; return 0; }").unwrap();
+
+    assert!(asm.contains("  lea rdi, [rip + .LC0]\n"));
+    assert!(asm.contains(".section .rodata\n.LC0:\n  .string \"hi\\012\"\n.LC1:\n  .string \"\\\"\"\n"));
+}
+
+// 宣言だけの関数はそのまま呼び出し, extern変数の領域は確保しない
+#[test]
+fn compile_extern_call() {
+    let asm = compile("extern int g; int puts(char *s); int main() { puts(\"hi\"); return g; }").unwrap();
+
+    assert!(asm.contains("  mov eax, 0\n  call puts\n"));
+    assert!(asm.contains("[rip + g]"));
+    assert!(!asm.contains("g:\n"));
+    assert!(!asm.contains("puts:\n"));
+}
+
+// 7個目以降の引数はスタックで渡し, 呼び出し先はrbp + 16から読む
+#[test]
+fn compile_stack_args() {
+    let asm = compile("int f(int a, int b, int c, int d, int e, int g, int h) { return h; } int main() { return f(1, 2, 3, 4, 5, 6, 7); }").unwrap();
+
+    assert!(asm.contains("  movsxd r10, DWORD PTR [rbp + 16]\n"));
+    // 1個だけ積むときは16バイト境界に揃えるために8バイト空ける
+    assert!(asm.contains("  sub rsp, 8\n"));
+    assert!(asm.contains("  call f\n  add rsp, 16\n"));
+}
+
+// 引数と戻り値は型の幅に合わせて符号拡張し, 64bitの値はそのまま使う
+#[test]
+fn generator_param_ret_width() {
+    let code = vec![
+        Fn { fn_name: "foo".to_string(), params: vec![
+            Param::new(VirtualReg { id: 0 }, "p".to_string(), Width::Qword),
+            Param::new(VirtualReg { id: 1 }, "c".to_string(), Width::Byte),
+        ] },
+        Call { fn_name: "bar".to_string(), args: Vec::new(), ret_reg: VirtualReg { id: 2 }, width: Width::Qword },
+        Return { src: VirtualReg { id: 2 } },
+    ];
+    let vreg_to_loc = HashMap::from([
+        (VirtualReg { id: 0 }, Location::Reg(0)),
+        (VirtualReg { id: 1 }, Location::Reg(1)),
+        (VirtualReg { id: 2 }, Location::Reg(2)),
+    ]);
+
+    let mut out = Vec::new();
+    let mut generator = Generator::new(vec!["rdi", "rsi", "rdx"], code, Frame::from_lvar_map(HashMap::new()), &mut out);
+    generator.gen_fn(vreg_to_loc).unwrap();
+    let asm = String::from_utf8(out).unwrap();
+
+    assert!(asm.contains("  movsx rsi, sil\n"));
+    assert!(!asm.contains("movsxd"));
+}
+
+// 任意のWriteに書き出せる
+#[test]
+fn generator_to_writer() {
+    let code = vec![
+        Fn { fn_name: "foo".to_string(), params: Vec::new() },
+        LoadImm { dest: VirtualReg { id: 0 }, value: 3 },
+        Return { src: VirtualReg { id: 0 } },
+    ];
+    let vreg_to_loc = HashMap::from([(VirtualReg { id: 0 }, Location::Reg(0))]);
+
+    let mut out = Vec::new();
+    let mut generator = Generator::new(vec!["rdi"], code, Frame::from_lvar_map(HashMap::new()), &mut out);
+    generator.gen_fn(vreg_to_loc).unwrap();
+
+    let expected = "\
+.globl foo
+foo:
+  push rbp
+  mov rbp, rsp
+  sub rsp, 0
+  mov rdi, 3
+  mov rax, rdi
+  mov rsp, rbp
+  pop rbp
+  ret
+";
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+}
+
+// 構文エラーはプロセスを終了せずにDiagnosticとして返る
+#[test]
+fn compile_error_diagnostic() {
+    use nonicc::diagnostic::{Diagnostics, ErrorCode};
+
+    let e = compile("int main() { return 1 + ; }").unwrap_err();
+    let d = e.downcast_ref::<Diagnostics>().unwrap();
+    assert_eq!(d.0[0].span.start, 24);
+    assert_eq!(d.0[0].code, ErrorCode::ExpectedNumber);
+}
@@ -1,5 +1,5 @@
 use nonicc::ir::gen_ir::{stmt_to_ir, GenIrContext};
-use nonicc::ir::types_ir::{ VirtualReg, BinOp::*, ThreeAddressCode::*, Param };
+use nonicc::ir::types_ir::{ VirtualReg, BinOp::*, ThreeAddressCode::*, Param, Width };
 use nonicc::parser::Parser;
 use nonicc::lexer::{ Tokenizer, TokenStream };
 
@@ -8,11 +8,11 @@ use nonicc::lexer::{ Tokenizer, TokenStream };
 fn ir_add() {
     let input = " int main() {1 + 1;} ";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let tokens = TokenStream::new(tok_vec, input);
 
     let mut parser = Parser::new(tokens);
-    let node = parser.defun();
+    let node = parser.defun().unwrap();
     let mut context = GenIrContext::new();
     stmt_to_ir(&node, &mut context);
 
@@ -21,7 +21,7 @@ fn ir_add() {
         Fn { fn_name: "main".to_string(), params: Vec::new() }, 
         LoadImm { dest: VirtualReg { id: 0 }, value: 1 }, 
         LoadImm { dest: VirtualReg { id: 1 }, value: 1 }, 
-        BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 0 }, op: Add, right: VirtualReg { id: 1 } }
+        BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 0 }, op: Add, right: VirtualReg { id: 1 }, width: Width::Dword }
     ];
 
     assert_eq!(output_ir, expected);
@@ -32,11 +32,11 @@ fn ir_add() {
 fn ir_basic_op() {
     let input = " int main() {1 + 2 - 3 * 4 / 5;} ";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let tokens = TokenStream::new(tok_vec, input);
 
     let mut parser = Parser::new(tokens);
-    let node = parser.defun();
+    let node = parser.defun().unwrap();
     let mut context = GenIrContext::new();
     stmt_to_ir(&node, &mut context);
 
@@ -45,13 +45,13 @@ fn ir_basic_op() {
         Fn { fn_name: "main".to_string(), params: Vec::new() }, 
         LoadImm { dest: VirtualReg { id: 0 }, value: 1 }, 
         LoadImm { dest: VirtualReg { id: 1 }, value: 2 }, 
-        BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 0 }, op: Add, right: VirtualReg { id: 1 } }, 
+        BinOpCode { dest: VirtualReg { id: 2 }, left: VirtualReg { id: 0 }, op: Add, right: VirtualReg { id: 1 }, width: Width::Dword }, 
         LoadImm { dest: VirtualReg { id: 3 }, value: 3 }, 
         LoadImm { dest: VirtualReg { id: 4 }, value: 4 }, 
-        BinOpCode { dest: VirtualReg { id: 5 }, left: VirtualReg { id: 3 }, op: Mul, right: VirtualReg { id: 4 } }, 
+        BinOpCode { dest: VirtualReg { id: 5 }, left: VirtualReg { id: 3 }, op: Mul, right: VirtualReg { id: 4 }, width: Width::Dword }, 
         LoadImm { dest: VirtualReg { id: 6 }, value: 5 }, 
-        BinOpCode { dest: VirtualReg { id: 7 }, left: VirtualReg { id: 5 }, op: Div, right: VirtualReg { id: 6 } }, 
-        BinOpCode { dest: VirtualReg { id: 8 }, left: VirtualReg { id: 2 }, op: Sub, right: VirtualReg { id: 7 } }
+        BinOpCode { dest: VirtualReg { id: 7 }, left: VirtualReg { id: 5 }, op: Div, right: VirtualReg { id: 6 }, width: Width::Dword }, 
+        BinOpCode { dest: VirtualReg { id: 8 }, left: VirtualReg { id: 2 }, op: Sub, right: VirtualReg { id: 7 }, width: Width::Dword }
     ];
 
     assert_eq!(output_ir, expected);
@@ -62,17 +62,17 @@ fn ir_basic_op() {
 fn ir_function_with_params() {
     let input = " int foo(int a, int b) { a; b; return 42; } ";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let tokens = TokenStream::new(tok_vec, input);
 
     let mut parser = Parser::new(tokens);
-    let node = parser.defun();
+    let node = parser.defun().unwrap();
     let mut context = GenIrContext::new();
     stmt_to_ir(&node, &mut context);
 
     let output_ir = context.get_ir_code();
     let expected = vec![
-        Fn { fn_name: "foo".to_string(), params: vec![Param { dest: VirtualReg { id: 0 }, name: "a".to_string() }, Param { dest: VirtualReg { id: 1 }, name: "b".to_string() }] },
+        Fn { fn_name: "foo".to_string(), params: vec![Param { dest: VirtualReg { id: 0 }, name: "a".to_string(), width: Width::Dword }, Param { dest: VirtualReg { id: 1 }, name: "b".to_string(), width: Width::Dword }] },
         EvalVar { dest: VirtualReg { id: 0 }, name: "a".to_string() },
         EvalVar { dest: VirtualReg { id: 1 }, name: "b".to_string() },
         LoadImm { dest: VirtualReg { id: 2 }, value: 42 },
@@ -80,4 +80,205 @@ fn ir_function_with_params() {
     ];
 
     assert_eq!(output_ir, expected);
-}
\ No newline at end of file
+}
+
+// デバッグ情報を付けると文ごとに位置が入るテスト
+#[test]
+fn ir_loc() {
+    use nonicc::types::Span;
+
+    let input = "int main() { int a; a = 1; return a; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let node = parser.defun().unwrap();
+    let mut context = GenIrContext::new();
+    context.debug_info = true;
+    stmt_to_ir(&node, &mut context);
+
+    let output_ir = context.get_ir_code();
+    let span_of = |s: &str| {
+        let start = input.find(s).unwrap();
+        Span { start, end: start + s.len() }
+    };
+    let expected = vec![
+        Fn { fn_name: "main".to_string(), params: Vec::new() },
+        Loc { span: Span { start: 0, end: input.len() } },
+        Loc { span: span_of("a = 1;") },
+        LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        Assign { dest: VirtualReg { id: 0 }, src: VirtualReg { id: 1 } },
+        Loc { span: span_of("return a;") },
+        EvalVar { dest: VirtualReg { id: 0 }, name: "a".to_string() },
+        Return { src: VirtualReg { id: 0 } },
+    ];
+
+    assert_eq!(output_ir, expected);
+}
+
+// ポインタの足し算は指す先の大きさ倍, 引き算は大きさで割るテスト
+#[test]
+fn ir_pointer_arith() {
+    use nonicc::sema;
+
+    let input = "int main() { int x; int *p; p = &x; p = p + 1; return p - &x; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let mut program = parser.program().unwrap();
+    sema::check(&mut program).unwrap();
+    let mut context = GenIrContext::new();
+    stmt_to_ir(&program[0], &mut context);
+
+    let output_ir = context.get_ir_code();
+    let x = VirtualReg { id: 0 };
+    let p = VirtualReg { id: 1 };
+    let expected = vec![
+        Fn { fn_name: "main".to_string(), params: Vec::new() },
+        AddrOf { addr: VirtualReg { id: 2 }, var: x },
+        Assign { dest: p, src: VirtualReg { id: 2 } },
+        // p + 1
+        EvalVar { dest: p, name: "p".to_string() },
+        LoadImm { dest: VirtualReg { id: 3 }, value: 1 },
+        LoadImm { dest: VirtualReg { id: 4 }, value: 4 },
+        BinOpCode { dest: VirtualReg { id: 5 }, left: VirtualReg { id: 3 }, op: Mul, right: VirtualReg { id: 4 }, width: Width::Qword },
+        BinOpCode { dest: VirtualReg { id: 6 }, left: p, op: Add, right: VirtualReg { id: 5 }, width: Width::Qword },
+        Assign { dest: p, src: VirtualReg { id: 6 } },
+        // p - &x
+        EvalVar { dest: p, name: "p".to_string() },
+        AddrOf { addr: VirtualReg { id: 7 }, var: x },
+        BinOpCode { dest: VirtualReg { id: 8 }, left: p, op: Sub, right: VirtualReg { id: 7 }, width: Width::Qword },
+        LoadImm { dest: VirtualReg { id: 9 }, value: 4 },
+        BinOpCode { dest: VirtualReg { id: 10 }, left: VirtualReg { id: 8 }, op: Div, right: VirtualReg { id: 9 }, width: Width::Qword },
+        Return { src: VirtualReg { id: 10 } },
+    ];
+
+    assert_eq!(output_ir, expected);
+}
+
+// グローバル変数は名前で読み書きし, 同じ名前のローカル変数を優先するテスト
+#[test]
+fn ir_global() {
+    use nonicc::sema;
+    use nonicc::types::Type;
+
+    let input = "int g; int main() { int *p; g = 1; p = &g; int g; g = 2; return g; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let mut program = parser.program().unwrap();
+    sema::check(&mut program).unwrap();
+    let mut context = GenIrContext::new();
+    context.globals.insert("g".to_string(), Type::Int);
+    stmt_to_ir(&program[1], &mut context);
+
+    let output_ir = context.get_ir_code();
+    let p = VirtualReg { id: 0 };
+    let g = VirtualReg { id: 3 };
+    let expected = vec![
+        Fn { fn_name: "main".to_string(), params: Vec::new() },
+        LoadImm { dest: VirtualReg { id: 1 }, value: 1 },
+        StoreGlobal { name: "g".to_string(), src: VirtualReg { id: 1 }, width: Width::Dword },
+        GlobalAddr { dest: VirtualReg { id: 2 }, name: "g".to_string() },
+        Assign { dest: p, src: VirtualReg { id: 2 } },
+        LoadImm { dest: VirtualReg { id: 4 }, value: 2 },
+        Assign { dest: g, src: VirtualReg { id: 4 } },
+        EvalVar { dest: g, name: "g".to_string() },
+        Return { src: g },
+    ];
+
+    assert_eq!(output_ir, expected);
+}
+
+// SSA形式への変換と復元のテスト
+#[test]
+fn ir_ssa_round_trip() {
+    use nonicc::ir::{ cfg::Cfg, ssa };
+
+    let input = " int main() { int x; x = 1; if (x) x = 2; return x; } ";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let node = parser.defun().unwrap();
+    let mut context = GenIrContext::new();
+    stmt_to_ir(&node, &mut context);
+
+    let mut cfg = Cfg::new(&context.get_ir_code());
+    let mut lvar_map = context.get_lvar_map();
+    ssa::construct(&mut cfg, &mut lvar_map);
+
+    // 変数はスタックに置かなくて良い
+    assert!(lvar_map.is_empty());
+    let code = cfg.to_code();
+    assert_eq!(code.iter().filter(|c| matches!(c, Phi { .. })).count(), 1);
+    assert!(!code.iter().any(|c| matches!(c, EvalVar { .. })));
+
+    ssa::destruct(&mut cfg);
+    let code = cfg.to_code();
+    assert!(!code.iter().any(|c| matches!(c, Phi { .. })));
+}
+
+// 定数畳み込みのテスト
+// 変数を通しても定数が伝わり, 条件が定数の分岐は消える
+#[test]
+fn ir_const_prop() {
+    use nonicc::ir::{ cfg::Cfg, ssa };
+    use nonicc::opt::const_prop;
+
+    let input = " int main() { int x; x = 1 + 2 * 3; if (x == 7) return x; return 0; } ";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let node = parser.defun().unwrap();
+    let mut context = GenIrContext::new();
+    stmt_to_ir(&node, &mut context);
+
+    let mut cfg = Cfg::new(&context.get_ir_code());
+    let mut lvar_map = context.get_lvar_map();
+    ssa::construct(&mut cfg, &mut lvar_map);
+    assert!(const_prop::run(&mut cfg));
+
+    let code = cfg.to_code();
+    assert!(!code.iter().any(|c| matches!(c, BinOpCode { .. } | IfFalse { .. })));
+    let Some(Return { src }) = code.iter().find(|c| matches!(c, Return { .. })) else {
+        panic!("return not found");
+    };
+    assert!(code.contains(&LoadImm { dest: *src, value: 7 }));
+}
+
+// 不要コード除去のテスト
+// 式文の結果とreturnの後ろの命令は消える
+#[test]
+fn ir_dce() {
+    use nonicc::opt::dce;
+    use nonicc::ir::cfg::Cfg;
+
+    let input = " int main() { 5; 4; 3; return 0; return 1; } ";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let tokens = TokenStream::new(tok_vec, input);
+
+    let mut parser = Parser::new(tokens);
+    let node = parser.defun().unwrap();
+    let mut context = GenIrContext::new();
+    stmt_to_ir(&node, &mut context);
+
+    let mut cfg = Cfg::new(&context.get_ir_code());
+    assert!(dce::run(&mut cfg));
+
+    let expected = vec![
+        Fn { fn_name: "main".to_string(), params: Vec::new() },
+        LoadImm { dest: VirtualReg { id: 3 }, value: 0 },
+        Return { src: VirtualReg { id: 3 } },
+    ];
+    assert_eq!(cfg.to_code(), expected);
+}
@@ -1,7 +1,7 @@
 use nonicc::{
     lexer::{TokenStream, Tokenizer}, 
     parser::Parser,
-    types::{Stmt::*, Type::*},
+    types::{ExprKind, Span, Stmt, StmtKind::*, Type::*},
 };
 
 
@@ -9,20 +9,20 @@ use nonicc::{
 fn parse_int_decl() {
     let input = "int main() { int a; }";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let token_stream = TokenStream::new(tok_vec, input);
     let mut parser = Parser::new(token_stream);
     let mut asts = Vec::new();
     while !parser.tokens.is_eof() {
-        asts.push(parser.defun());
+        asts.push(parser.defun().unwrap());
     }
     eprintln!("[DEBUG] asts: {:?}", asts);
     let expected = vec![
-        Fn { 
+        Stmt::new(Fn { 
             fn_name: String::from("main"), 
             params: vec![], 
-            body: vec![VarDecl { name: String::from("a"), ty: Int }] 
-        }
+            body: vec![Stmt::new(VarDecl { name: String::from("a"), ty: Int }, Span { start: 13, end: 19 })] 
+        }, Span { start: 0, end: 21 })
     ];
     assert_eq!(asts, expected);
 }
@@ -31,20 +31,20 @@ fn parse_int_decl() {
 fn parse_int_pointer_decl() {
     let input = "int main() { int *a; }";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let token_stream = TokenStream::new(tok_vec, input);
     let mut parser = Parser::new(token_stream);
     let mut asts = Vec::new();
     while !parser.tokens.is_eof() {
-        asts.push(parser.defun());
+        asts.push(parser.defun().unwrap());
     }
     eprintln!("[DEBUG] asts: {:?}", asts);
     let expected = vec![
-        Fn { 
+        Stmt::new(Fn { 
             fn_name: String::from("main"), 
             params: Vec::new(), 
-            body: vec![VarDecl { name: String::from("a"), ty: Ptr(Box::new(Int)) }] 
-        }
+            body: vec![Stmt::new(VarDecl { name: String::from("a"), ty: Ptr(Box::new(Int)) }, Span { start: 13, end: 20 })] 
+        }, Span { start: 0, end: 22 })
     ];
     assert_eq!(asts, expected);
 }
@@ -53,20 +53,272 @@ fn parse_int_pointer_decl() {
 fn parse_int_pointer_pointer_decl() {
     let input = "int main() { int **a; }";
     let mut tokenizer = Tokenizer::new(input);
-    let tok_vec = tokenizer.tokenize();
+    let tok_vec = tokenizer.tokenize().unwrap();
     let token_stream = TokenStream::new(tok_vec, input);
     let mut parser = Parser::new(token_stream);
     let mut asts = Vec::new();
     while !parser.tokens.is_eof() {
-        asts.push(parser.defun());
+        asts.push(parser.defun().unwrap());
     }
     eprintln!("[DEBUG] asts: {:?}", asts);
     let expected = vec![
-        Fn { 
+        Stmt::new(Fn { 
             fn_name: String::from("main"), 
             params: Vec::new(), 
-            body: vec![VarDecl { name: String::from("a"), ty: Ptr(Box::new(Ptr(Box::new(Int)))) }] 
-        }
+            body: vec![Stmt::new(VarDecl { name: String::from("a"), ty: Ptr(Box::new(Ptr(Box::new(Int)))) }, Span { start: 13, end: 21 })] 
+        }, Span { start: 0, end: 23 })
     ];
     assert_eq!(asts, expected);
-}
\ No newline at end of file
+}
+
+#[test]
+fn parse_missing_semicolon() {
+    let input = "int main() { return 1 }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    // プロセスを終了せずにエラーを返す
+    let e = parser.defun().unwrap_err();
+    assert_eq!(e.len(), 1);
+    assert_eq!(e[0].message, "';'が必要です");
+    assert_eq!(e[0].span, Span { start: 22, end: 23 });
+}
+
+#[test]
+fn parse_recover_multiple_errors() {
+    // 文の途中, ブロックの中, 関数の宣言部分のエラーをそれぞれ報告する
+    let input = "int main() { int a; a = ; { return 1 } a = 2 int b; return a; } int foo( { return 0; } int bar() { return 3; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let errors = parser.program().unwrap_err();
+    let positions: Vec<usize> = errors.iter().map(|e| e.span.start).collect();
+    let expected: Vec<usize> = vec![
+        input.find("; {").unwrap(),
+        input.find("} a").unwrap(),
+        input.find("int b").unwrap(),
+        input.find("{ return 0").unwrap(),
+    ];
+    assert_eq!(positions, expected);
+}
+
+#[test]
+fn parse_expr_span() {
+    // 式と文はそれぞれソースコード上の範囲を持つ
+    let input = "int main() { return (1 + 2) * 3; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let node = parser.defun().unwrap();
+    let body = match node.kind {
+        Fn { body, .. } => body,
+        _ => panic!("expected function"),
+    };
+    let span_of = |s: &str| {
+        let start = input.find(s).unwrap();
+        Span { start, end: start + s.len() }
+    };
+    assert_eq!(body[0].span, span_of("return (1 + 2) * 3;"));
+    match &body[0].kind {
+        Return(expr) => {
+            assert_eq!(expr.span, span_of("(1 + 2) * 3"));
+            match &expr.kind {
+                ExprKind::Binary { lhs, rhs, .. } => {
+                    assert_eq!(lhs.span, span_of("(1 + 2)"));
+                    assert_eq!(rhs.span, span_of("3"));
+                }
+                _ => panic!("expected binary expression"),
+            }
+        }
+        _ => panic!("expected return"),
+    }
+}
+
+#[test]
+fn parse_array_subscript() {
+    // 配列の宣言と`a[i]`を`*(a + i)`として読む
+    let input = "int main() { int a[2][3]; a[1]; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let node = parser.defun().unwrap();
+    let body = match node.kind {
+        Fn { body, .. } => body,
+        _ => panic!("expected function"),
+    };
+    let ty = Array(Box::new(Array(Box::new(Int), 3)), 2);
+    assert_eq!(body[0].kind, VarDecl { name: String::from("a"), ty });
+    match &body[1].kind {
+        ExprStmt(expr) => match &expr.kind {
+            ExprKind::Deref(addr) => match &addr.kind {
+                ExprKind::Binary { lhs, rhs, .. } => {
+                    assert_eq!(lhs.kind, ExprKind::Var(String::from("a")));
+                    assert_eq!(rhs.kind, ExprKind::Num(1));
+                }
+                _ => panic!("expected binary expression"),
+            },
+            _ => panic!("expected deref"),
+        },
+        _ => panic!("expected expression statement"),
+    }
+}
+
+#[test]
+fn parse_member_arrow() {
+    // `p->x`は`(*p).x`として読む
+    let input = "int main() { struct P { int x; } *p; p->x; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let node = parser.defun().unwrap();
+    let body = match node.kind {
+        Fn { body, .. } => body,
+        _ => panic!("expected function"),
+    };
+    match &body[1].kind {
+        ExprStmt(expr) => match &expr.kind {
+            ExprKind::Member { base, name } => {
+                assert_eq!(name, "x");
+                match &base.kind {
+                    ExprKind::Deref(p) => assert_eq!(p.kind, ExprKind::Var(String::from("p"))),
+                    _ => panic!("expected deref"),
+                }
+            }
+            _ => panic!("expected member access"),
+        },
+        _ => panic!("expected expression statement"),
+    }
+}
+
+#[test]
+fn parse_global_var() {
+    // 関数の外の宣言はグローバル変数になる
+    let input = "int g; int *p = 0; int main() { return g; } char c = -1;";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let program = parser.program().unwrap();
+    let kinds: Vec<&nonicc::types::StmtKind> = program.iter().map(|s| &s.kind).collect();
+    assert_eq!(kinds[0], &GlobalVar { name: String::from("g"), ty: Int, init: None, is_extern: false });
+    assert_eq!(kinds[1], &GlobalVar { name: String::from("p"), ty: Ptr(Box::new(Int)), init: Some(0), is_extern: false });
+    assert!(matches!(kinds[2], Fn { .. }));
+    assert_eq!(kinds[3], &GlobalVar { name: String::from("c"), ty: Char, init: Some(-1), is_extern: false });
+}
+
+#[test]
+fn parse_prototype_and_extern() {
+    // プロトタイプ宣言した関数は定義の前に呼び出せる
+    // 宣言はカンマで区切って並べられる
+    let input = "extern int g, f(int, char *p, ...); int main() { return f(g, 0); }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let program = parser.program().unwrap();
+    let kinds: Vec<&nonicc::types::StmtKind> = program.iter().map(|s| &s.kind).collect();
+    assert_eq!(kinds[0], &GlobalVar { name: String::from("g"), ty: Int, init: None, is_extern: true });
+    assert_eq!(kinds[1], &FnDecl { fn_name: String::from("f"), params: Some(vec![Int, Ptr(Box::new(Char))]), variadic: true });
+    assert!(matches!(kinds[2], Fn { .. }));
+
+    // `()`は引数を決めない宣言になる
+    let input = "int h();";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let program = parser.program().unwrap();
+    assert_eq!(program[0].kind, FnDecl { fn_name: String::from("h"), params: None, variadic: false });
+}
+
+#[test]
+fn parse_self_referential_struct() {
+    // 定義の中や前方宣言の後では, 同じタグは同じ型を指す
+    let input = "struct Node { int v; struct Node *next; }; struct S; struct S *p; struct S { int a; } s; struct U { struct U u; };";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let errors = parser.program().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "field has incomplete type 'struct U'");
+
+    let input = "struct Node { int v; struct Node *next; } n; struct S; struct S *p; struct S { int a; } s;";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let program = parser.program().unwrap();
+    let types: Vec<&nonicc::types::Type> = program.iter().map(|s| match &s.kind {
+        GlobalVar { ty, .. } => ty,
+        _ => panic!("expected global variable"),
+    }).collect();
+    let Struct(node) = types[0] else { panic!("expected struct") };
+    assert_eq!(node.member("next").unwrap().ty, Ptr(Box::new(types[0].clone())));
+    assert_eq!(types[1], &Ptr(Box::new(types[2].clone())));
+    assert_eq!(types[2].size(), 4);
+}
+
+#[test]
+fn parse_struct_redefinition() {
+    // 定義済みのタグは定義し直せないが, 前方宣言したタグは定義できる
+    let input = "struct S; struct S { int a; }; struct S { char c; }; struct S s; int main() { return 0; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let errors = parser.program().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "redefinition of 'struct S'");
+    let start = input.rfind("S { char").unwrap();
+    assert_eq!(errors[0].span, Span { start, end: start + 1 });
+}
+
+#[test]
+fn parse_struct_param() {
+    // 構造体の値渡しはできないので, プロトタイプ宣言でも弾く
+    let input = "struct S { int a; }; int f(struct S s, int x); int g(struct S *p); int main() { return 0; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let errors = parser.program().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "passing 'struct S' by value is not supported");
+    let start = input.find("struct S s").unwrap();
+    assert_eq!(errors[0].span, Span { start, end: start + "struct S s".len() });
+}
+
+#[test]
+fn parse_typed_params() {
+    // 関数定義の引数は宣言した型を持ち, 配列はポインタになる
+    let input = "int f(int *p, char c, int a[2]) { return 0; } int g(struct S s) { return 0; }";
+    let mut tokenizer = Tokenizer::new(input);
+    let tok_vec = tokenizer.tokenize().unwrap();
+    let token_stream = TokenStream::new(tok_vec, input);
+    let mut parser = Parser::new(token_stream);
+
+    let Fn { params, .. } = parser.defun().unwrap().kind else { panic!("expected function") };
+    let types: Vec<_> = params.iter().map(|p| p.ty.clone().unwrap()).collect();
+    assert_eq!(types, vec![Ptr(Box::new(Int)), Char, Ptr(Box::new(Int))]);
+
+    let errors = parser.defun().unwrap_err();
+    assert_eq!(errors[0].message, "passing 'struct S' by value is not supported");
+}
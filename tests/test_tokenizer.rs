@@ -6,7 +6,7 @@ use nonicc::lexer::Tokenizer;
 #[test]
 fn tokenize_single_number() {
     let mut tokinizer = Tokenizer::new("0;");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_NUM, val: Some(0), str: "0".to_string(), len: 1, pos: 0 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 1 }, 
@@ -19,7 +19,7 @@ fn tokenize_single_number() {
 #[test]
 fn tokenize_number_with_whitespace() {
     let mut tokinizer = Tokenizer::new(" 42; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_NUM, val: Some(42), str: "42".to_string(), len: 2, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 3 }, 
@@ -32,7 +32,7 @@ fn tokenize_number_with_whitespace() {
 #[test]
 fn tokenize_string_with_whitespace() {
     let mut tokinizer = Tokenizer::new(" foo; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "foo".to_string(), len: 3, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 4 }, 
@@ -46,7 +46,7 @@ fn tokenize_string_with_whitespace() {
 #[test]
 fn tokenize_ambiguous_equal() {
     let mut tokinizer = Tokenizer::new(" == == = ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_RESERVED, val: None, str: "==".to_string(), len: 2, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: "==".to_string(), len: 2, pos: 4 }, 
@@ -60,7 +60,7 @@ fn tokenize_ambiguous_equal() {
 #[test]
 fn tokenize_ambiguous_inequal() {
     let mut tokinizer = Tokenizer::new(" < <= >= > ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_RESERVED, val: None, str: "<".to_string(), len: 1, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: "<=".to_string(), len: 2, pos: 3 }, 
@@ -75,7 +75,7 @@ fn tokenize_ambiguous_inequal() {
 #[test]
 fn tokenize_return() {
     let mut tokinizer = Tokenizer::new(" return ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_RETURN, val: None, str: "return".to_string(), len: 6, pos: 1 }, 
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 8 }
@@ -87,7 +87,7 @@ fn tokenize_return() {
 #[test]
 fn tokenize_returnx() {
     let mut tokinizer = Tokenizer::new(" returnx ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "returnx".to_string(), len: 7, pos: 1 }, 
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 9 }
@@ -99,7 +99,7 @@ fn tokenize_returnx() {
 #[test]
 fn tokenize_if() {
     let mut tokinizer = Tokenizer::new(" if ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IF, val: None, str: "if".to_string(), len: 2, pos: 1 }, 
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 4 }
@@ -111,7 +111,7 @@ fn tokenize_if() {
 #[test]
 fn tokenize_ifx() {
     let mut tokinizer = Tokenizer::new(" ifx ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "ifx".to_string(), len: 3, pos: 1 }, 
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 5 }
@@ -123,7 +123,7 @@ fn tokenize_ifx() {
 #[test]
 fn tokenize_ident_with_num() {
     let mut tokinizer = Tokenizer::new(" A2b_C3; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "A2b_C3".to_string(), len: 6, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 7 }, 
@@ -136,7 +136,7 @@ fn tokenize_ident_with_num() {
 #[test]
 fn tokenize_while() {
     let mut tokinizer = Tokenizer::new(" while; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_WHILE, val: None, str: "while".to_string(), len: 5, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 6 }, 
@@ -149,7 +149,7 @@ fn tokenize_while() {
 #[test]
 fn tokenize_whilex() {
     let mut tokinizer = Tokenizer::new(" whilex; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "whilex".to_string(), len: 6, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 7 }, 
@@ -162,7 +162,7 @@ fn tokenize_whilex() {
 #[test]
 fn tokenize_for() {
     let mut tokinizer = Tokenizer::new(" for; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_FOR, val: None, str: "for".to_string(), len: 3, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 4 }, 
@@ -175,7 +175,7 @@ fn tokenize_for() {
 #[test]
 fn tokenize_forx() {
     let mut tokinizer = Tokenizer::new(" forx; ");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "forx".to_string(), len: 4, pos: 1 }, 
         Token { kind: TK_RESERVED, val: None, str: ";".to_string(), len: 1, pos: 5 }, 
@@ -188,7 +188,7 @@ fn tokenize_forx() {
 #[test]
 fn tokenize_type_int() {
     let mut tokinizer = Tokenizer::new("int");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_TYPE(TypeKind::Int), val: None, str: String::from("int"), len: 3, pos: 0 },
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 3 }
@@ -196,13 +196,65 @@ fn tokenize_type_int() {
     assert_eq!(tokens, expected);
 }
 
+#[test]
+fn tokenize_type_char() {
+    let mut tokinizer = Tokenizer::new("char chars");
+    let tokens = tokinizer.tokenize().unwrap();
+    let expected = vec![
+        Token { kind: TK_TYPE(TypeKind::Char), val: None, str: String::from("char"), len: 4, pos: 0 },
+        Token { kind: TK_IDENT, val: None, str: String::from("chars"), len: 5, pos: 5 },
+        Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 10 }
+    ];
+    assert_eq!(tokens, expected);
+}
+
 #[test]
 fn tokenize_int_() {
     let mut tokinizer = Tokenizer::new("int_");
-    let tokens = tokinizer.tokenize();
+    let tokens = tokinizer.tokenize().unwrap();
     let expected = vec![
         Token { kind: TK_IDENT, val: None, str: "int_".to_string(), len: 4, pos: 0 }, 
         Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 4 }
     ];
     assert_eq!(tokens, expected);
-}
\ No newline at end of file
+}
+
+/// トークナイズできない文字はエラーになる
+#[test]
+fn tokenize_invalid_char() {
+    use nonicc::types::Span;
+
+    let mut tokinizer = Tokenizer::new("1 @ 2");
+    let e = tokinizer.tokenize().unwrap_err();
+    assert_eq!(e.span, Span { start: 2, end: 3 });
+
+    // 非ASCII文字は1文字全体を範囲にする
+    let mut tokinizer = Tokenizer::new("1 é 2");
+    let e = tokinizer.tokenize().unwrap_err();
+    assert_eq!(e.span, Span { start: 2, end: 4 });
+}
+
+/// 文字列リテラルはエスケープを展開したバイト列を持つ
+/// 文字リテラルは数値になる
+#[test]
+fn tokenize_string_and_char_literal() {
+    let mut tokinizer = Tokenizer::new(r#""a\t\"\101\x4a" '\n' '\xff'"#);
+    let tokens = tokinizer.tokenize().unwrap();
+    let expected = vec![
+        Token { kind: TK_STR(b"a\t\"AJ".to_vec()), val: None, str: r#""a\t\"\101\x4a""#.to_string(), len: 15, pos: 0 },
+        Token { kind: TK_NUM, val: Some(10), str: r"'\n'".to_string(), len: 4, pos: 16 },
+        Token { kind: TK_NUM, val: Some(-1), str: r"'\xff'".to_string(), len: 6, pos: 21 },
+        Token { kind: TK_EOF, val: None, str: "<EOF>".to_string(), len: 1, pos: 27 }
+    ];
+    assert_eq!(tokens, expected);
+}
+
+/// 閉じられていない文字列リテラルはエラーになる
+#[test]
+fn tokenize_unterminated_string() {
+    use nonicc::diagnostic::ErrorCode;
+
+    let mut tokinizer = Tokenizer::new("\"abc\n\";");
+    let e = tokinizer.tokenize().unwrap_err();
+    assert_eq!(e.code, ErrorCode::UnterminatedLiteral);
+}